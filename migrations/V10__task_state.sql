@@ -0,0 +1,36 @@
+-- latest state of every task, so reads don't have to scan whole journal
+CREATE TABLE task_state AS
+    SELECT DISTINCT ON (task_id) * FROM tasks ORDER BY task_id, id DESC;
+
+ALTER TABLE task_state ADD PRIMARY KEY (task_id);
+
+CREATE INDEX task_state_status_idx ON task_state (status);
+CREATE INDEX task_state_runnable_idx ON task_state (priority DESC, task_id) WHERE status IN ('pending', 'failed');
+CREATE INDEX parents_parent_id_idx ON parents (parent_id);
+
+-- keeps task_state in sync with journal, in the same transaction
+CREATE FUNCTION update_task_state() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO task_state (id, task_id, status, timestamp, data, params, attempt, retry_at, heartbeat, claim_token, blocked_by, priority)
+    VALUES (NEW.id, NEW.task_id, NEW.status, NEW.timestamp, NEW.data, NEW.params, NEW.attempt, NEW.retry_at, NEW.heartbeat, NEW.claim_token, NEW.blocked_by, NEW.priority)
+    ON CONFLICT (task_id) DO UPDATE SET
+        id = EXCLUDED.id,
+        status = EXCLUDED.status,
+        timestamp = EXCLUDED.timestamp,
+        data = EXCLUDED.data,
+        params = EXCLUDED.params,
+        attempt = EXCLUDED.attempt,
+        retry_at = EXCLUDED.retry_at,
+        heartbeat = EXCLUDED.heartbeat,
+        claim_token = EXCLUDED.claim_token,
+        blocked_by = EXCLUDED.blocked_by,
+        priority = EXCLUDED.priority
+    -- only the latest row of the task is its state
+    WHERE task_state.id <= EXCLUDED.id;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER task_state_update AFTER INSERT OR UPDATE ON tasks
+    FOR EACH ROW EXECUTE FUNCTION update_task_state();
@@ -0,0 +1,32 @@
+-- job kind (variant name of serialized JobType, e.g. {"Blur":1.0} or "Input"), so tasks can be claimed by kind in SQL
+ALTER TABLE tasks ADD COLUMN kind TEXT GENERATED ALWAYS AS (substring(params from '^\{?"(\w+)"')) STORED;
+ALTER TABLE task_state ADD COLUMN kind TEXT;
+
+UPDATE task_state s SET kind = t.kind FROM tasks t WHERE t.id = s.id;
+
+DROP INDEX task_state_runnable_idx;
+CREATE INDEX task_state_runnable_idx ON task_state (kind, priority DESC, task_id) WHERE status IN ('pending', 'failed');
+
+CREATE OR REPLACE FUNCTION update_task_state() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO task_state (id, task_id, status, timestamp, data, params, attempt, retry_at, heartbeat, claim_token, blocked_by, priority, kind)
+    VALUES (NEW.id, NEW.task_id, NEW.status, NEW.timestamp, NEW.data, NEW.params, NEW.attempt, NEW.retry_at, NEW.heartbeat, NEW.claim_token, NEW.blocked_by, NEW.priority, NEW.kind)
+    ON CONFLICT (task_id) DO UPDATE SET
+        id = EXCLUDED.id,
+        status = EXCLUDED.status,
+        timestamp = EXCLUDED.timestamp,
+        data = EXCLUDED.data,
+        params = EXCLUDED.params,
+        attempt = EXCLUDED.attempt,
+        retry_at = EXCLUDED.retry_at,
+        heartbeat = EXCLUDED.heartbeat,
+        claim_token = EXCLUDED.claim_token,
+        blocked_by = EXCLUDED.blocked_by,
+        priority = EXCLUDED.priority,
+        kind = EXCLUDED.kind
+    -- only the latest row of the task is its state
+    WHERE task_state.id <= EXCLUDED.id;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,37 @@
+CREATE TYPE failure_kind_type AS ENUM('process_error', 'missing_input', 'timeout', 'output_lost', 'shutdown');
+
+-- why task failed (set only on failed and dead rows) and which worker was running it
+ALTER TABLE tasks ADD COLUMN failure_kind failure_kind_type;
+ALTER TABLE tasks ADD COLUMN error_message TEXT;
+ALTER TABLE tasks ADD COLUMN worker TEXT;
+
+ALTER TABLE task_state ADD COLUMN failure_kind failure_kind_type;
+ALTER TABLE task_state ADD COLUMN error_message TEXT;
+ALTER TABLE task_state ADD COLUMN worker TEXT;
+
+CREATE OR REPLACE FUNCTION update_task_state() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO task_state (id, task_id, status, timestamp, data, params, attempt, retry_at, heartbeat, claim_token, blocked_by, priority, kind, failure_kind, error_message, worker)
+    VALUES (NEW.id, NEW.task_id, NEW.status, NEW.timestamp, NEW.data, NEW.params, NEW.attempt, NEW.retry_at, NEW.heartbeat, NEW.claim_token, NEW.blocked_by, NEW.priority, NEW.kind, NEW.failure_kind, NEW.error_message, NEW.worker)
+    ON CONFLICT (task_id) DO UPDATE SET
+        id = EXCLUDED.id,
+        status = EXCLUDED.status,
+        timestamp = EXCLUDED.timestamp,
+        data = EXCLUDED.data,
+        params = EXCLUDED.params,
+        attempt = EXCLUDED.attempt,
+        retry_at = EXCLUDED.retry_at,
+        heartbeat = EXCLUDED.heartbeat,
+        claim_token = EXCLUDED.claim_token,
+        blocked_by = EXCLUDED.blocked_by,
+        priority = EXCLUDED.priority,
+        kind = EXCLUDED.kind,
+        failure_kind = EXCLUDED.failure_kind,
+        error_message = EXCLUDED.error_message,
+        worker = EXCLUDED.worker
+    -- only the latest row of the task is its state
+    WHERE task_state.id <= EXCLUDED.id;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
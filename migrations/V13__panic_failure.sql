@@ -0,0 +1 @@
+ALTER TYPE failure_kind_type ADD VALUE 'panic';
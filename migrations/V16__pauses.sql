@@ -0,0 +1,9 @@
+-- worker types paused by user, their tasks are neither claimed nor started until resumed
+CREATE TABLE paused_workers (
+    name       TEXT NOT NULL PRIMARY KEY
+);
+
+-- output tasks of paused pipelines, the task and all tasks it depends on are not claimed until resumed
+CREATE TABLE paused_pipelines (
+    task_id    BIGINT NOT NULL PRIMARY KEY
+);
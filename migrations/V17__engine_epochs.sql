@@ -0,0 +1,43 @@
+ALTER TYPE failure_kind_type ADD VALUE 'engine_died';
+
+-- every engine process registers new epoch and holds advisory lock with its number while it is alive
+CREATE TABLE engines (
+    epoch      BIGSERIAL NOT NULL PRIMARY KEY,
+    started    BIGINT NOT NULL,
+    stopped    BIGINT,
+    pid        INTEGER NOT NULL
+);
+
+-- engine that claimed the task (set only on claimed and running rows)
+ALTER TABLE tasks ADD COLUMN epoch BIGINT;
+ALTER TABLE task_state ADD COLUMN epoch BIGINT;
+
+CREATE INDEX task_state_epoch_idx ON task_state (epoch) WHERE status IN ('claimed', 'running');
+
+CREATE OR REPLACE FUNCTION update_task_state() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO task_state (id, task_id, status, timestamp, data, params, attempt, retry_at, heartbeat, claim_token, blocked_by, priority, kind, failure_kind, error_message, worker, epoch)
+    VALUES (NEW.id, NEW.task_id, NEW.status, NEW.timestamp, NEW.data, NEW.params, NEW.attempt, NEW.retry_at, NEW.heartbeat, NEW.claim_token, NEW.blocked_by, NEW.priority, NEW.kind, NEW.failure_kind, NEW.error_message, NEW.worker, NEW.epoch)
+    ON CONFLICT (task_id) DO UPDATE SET
+        id = EXCLUDED.id,
+        status = EXCLUDED.status,
+        timestamp = EXCLUDED.timestamp,
+        data = EXCLUDED.data,
+        params = EXCLUDED.params,
+        attempt = EXCLUDED.attempt,
+        retry_at = EXCLUDED.retry_at,
+        heartbeat = EXCLUDED.heartbeat,
+        claim_token = EXCLUDED.claim_token,
+        blocked_by = EXCLUDED.blocked_by,
+        priority = EXCLUDED.priority,
+        kind = EXCLUDED.kind,
+        failure_kind = EXCLUDED.failure_kind,
+        error_message = EXCLUDED.error_message,
+        worker = EXCLUDED.worker,
+        epoch = EXCLUDED.epoch
+    -- only the latest row of the task is its state
+    WHERE task_state.id <= EXCLUDED.id;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,41 @@
+-- lease is renewed on task_state only, rows of the journal are never changed
+ALTER TABLE tasks DROP COLUMN heartbeat;
+
+CREATE OR REPLACE FUNCTION update_task_state() RETURNS trigger AS $$
+BEGIN
+    -- new state starts without heartbeat, lease of running task counts from its start
+    INSERT INTO task_state (id, task_id, status, timestamp, data, params, attempt, retry_at, heartbeat, claim_token, blocked_by, priority, kind, failure_kind, error_message, worker, epoch)
+    VALUES (NEW.id, NEW.task_id, NEW.status, NEW.timestamp, NEW.data, NEW.params, NEW.attempt, NEW.retry_at, NULL, NEW.claim_token, NEW.blocked_by, NEW.priority, NEW.kind, NEW.failure_kind, NEW.error_message, NEW.worker, NEW.epoch)
+    ON CONFLICT (task_id) DO UPDATE SET
+        id = EXCLUDED.id,
+        status = EXCLUDED.status,
+        timestamp = EXCLUDED.timestamp,
+        data = EXCLUDED.data,
+        params = EXCLUDED.params,
+        attempt = EXCLUDED.attempt,
+        retry_at = EXCLUDED.retry_at,
+        heartbeat = NULL,
+        claim_token = EXCLUDED.claim_token,
+        blocked_by = EXCLUDED.blocked_by,
+        priority = EXCLUDED.priority,
+        kind = EXCLUDED.kind,
+        failure_kind = EXCLUDED.failure_kind,
+        error_message = EXCLUDED.error_message,
+        worker = EXCLUDED.worker,
+        epoch = EXCLUDED.epoch
+    -- only the latest row of the task is its state
+    WHERE task_state.id <= EXCLUDED.id;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- journal is append-only audit log
+CREATE FUNCTION reject_journal_change() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'tasks journal is append-only, % is not allowed', TG_OP;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_append_only BEFORE UPDATE OR DELETE ON tasks
+    FOR EACH ROW EXECUTE FUNCTION reject_journal_change();
@@ -0,0 +1,5 @@
+ALTER TYPE status_type ADD VALUE 'dead';
+
+-- number of times task was started and unix timestamp of next allowed retry
+ALTER TABLE tasks ADD COLUMN attempt  INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN retry_at BIGINT;
@@ -0,0 +1,2 @@
+-- unix timestamp of last lease renewal of running task
+ALTER TABLE tasks ADD COLUMN heartbeat BIGINT;
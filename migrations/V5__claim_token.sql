@@ -0,0 +1,3 @@
+-- fencing token of the claim, new one is taken for every claim of the task
+CREATE SEQUENCE claim_token_seq AS BIGINT;
+ALTER TABLE tasks ADD COLUMN claim_token BIGINT;
@@ -0,0 +1 @@
+ALTER TYPE status_type ADD VALUE 'cancelled';
@@ -0,0 +1,4 @@
+ALTER TYPE status_type ADD VALUE 'blocked';
+
+-- dead ancestor because of which task is blocked
+ALTER TABLE tasks ADD COLUMN blocked_by BIGINT;
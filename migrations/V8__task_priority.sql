@@ -0,0 +1,2 @@
+-- tasks with higher priority are claimed first
+ALTER TABLE tasks ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
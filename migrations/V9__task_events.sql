@@ -0,0 +1,11 @@
+-- wake up scheduler when task may have become runnable
+CREATE FUNCTION notify_task_event() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('task_events', NEW.task_id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER task_event AFTER INSERT ON tasks
+    FOR EACH ROW WHEN (NEW.status IN ('pending', 'failed', 'completed'))
+    EXECUTE FUNCTION notify_task_event();
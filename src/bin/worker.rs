@@ -0,0 +1,60 @@
+//! Standalone worker - runs only worker threads that claim tasks from database by themselves, without GUI and scheduler.
+//! Engine (GUI) has to run in other process to recover failed and timeouted tasks.
+
+use std::error::Error;
+
+use clap::Parser;
+use log::warn;
+
+use fault_tolerant_image_processing::{database, engine::run_standalone, processing};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of threads for worker1 (Resize, Crop, Overlay)
+    #[clap(long, default_value_t = 1)]
+    worker1_threads: usize,
+    /// Number of threads for worker2 (Blur, Brightness)
+    #[clap(long, default_value_t = 1)]
+    worker2_threads: usize,
+    /// Run jobs of every worker thread in a child process, so crash (abort, out of memory) of a job doesn't take down the worker
+    #[clap(long, default_value_t = false)]
+    isolate_workers: bool,
+    /// Serve jobs of given worker as child process of isolated worker thread (see --isolate-workers)
+    #[clap(long, hide = true, value_name = "NAME")]
+    worker_process: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // init .env
+    dotenvy::dotenv().ok();
+    dotenvy::from_filename(".env.local").ok();
+
+    env_logger::init();
+
+    let args = Args::parse();
+
+    // child process of isolated worker thread, it doesn't touch the database
+    if let Some(name) = &args.worker_process {
+        processing::worker::default_registry(1, 1).serve_worker_process(name)?;
+        return Ok(());
+    }
+
+    let mut workers = processing::worker::default_registry(args.worker1_threads, args.worker2_threads);
+    if args.isolate_workers {
+        workers = workers.run_in_processes();
+    }
+    // main prints errors with Debug, message tells how to fix it
+    database::common::init_pool(workers.thread_count()).map_err(|e| e.to_string())?;
+
+    let mut db = database::common::open_connection()?;
+    database::migration::run_migrations(&mut db);
+    // worker threads take their own connections
+    drop(db);
+
+    // outputs of tasks interrupted by crash are never published, only their staging files are left
+    if let Err(e) = processing::data_loader::remove_orphaned_staging_files() {
+        warn!("Unable to remove orphaned staging files: {}", e);
+    }
+
+    run_standalone(workers)
+}
@@ -6,7 +6,7 @@ mod embedded {
 }
 
 pub fn run_migrations(db: &mut Database) {
-    match embedded::migrations::runner().run(&mut db.conn) {
+    match embedded::migrations::runner().run(&mut **db) {
         Ok(_) => println!("Migrations ran successfully"),
         Err(e) => println!("Error running migrations: {:?}", e),
     }
@@ -0,0 +1,62 @@
+use crate::database::{
+    common::{Database, ErrorType},
+    repositories::task::get_timestamp,
+};
+
+/// Key of advisory lock held by engine that schedules tasks. Two-key form doesn't collide with epoch locks.
+const SCHEDULER_LOCK: (i32, i32) = (1, 0);
+
+/// Engine epochs. Every engine process registers new epoch and holds advisory lock with its number
+/// (session lock on its own connection) for as long as it is alive, so lock of dead engine is free.
+pub mod engine_querry {
+    use postgres::GenericClient;
+
+    use crate::database::{common::ErrorType, repositories::task::get_timestamp};
+
+    /// Tries to take lock of the epoch until end of current transaction, succeeds only if its engine is not alive.
+    pub fn try_lock_epoch(conn: &mut impl GenericClient, epoch: i64) -> Result<bool, ErrorType> {
+        let row = conn.query_one("SELECT pg_try_advisory_xact_lock($1)", &[&epoch])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    pub fn mark_epoch_as_stopped(conn: &mut impl GenericClient, epoch: i64) -> Result<(), ErrorType> {
+        conn.execute("UPDATE engines SET stopped = $2 WHERE epoch = $1 AND stopped IS NULL", &[&epoch, &get_timestamp()])?;
+
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Registers new engine epoch and locks it for as long as this connection is open.
+    pub fn register_engine(&mut self) -> Result<i64, ErrorType> {
+        const QUERY: &str = "INSERT INTO engines (started, pid) VALUES ($1, $2) RETURNING epoch";
+
+        let row = self.client()?.query_one(QUERY, &[&get_timestamp(), &(std::process::id() as i32)])?;
+        let epoch = row.try_get(0)?;
+
+        self.lock_engine_epoch(epoch)?;
+
+        Ok(epoch)
+    }
+
+    /// Takes lock of the epoch, held until this connection is closed. Used again after connection was lost.
+    pub fn lock_engine_epoch(&mut self, epoch: i64) -> Result<(), ErrorType> {
+        self.client()?.execute("SELECT pg_advisory_lock($1)", &[&epoch])?;
+
+        Ok(())
+    }
+
+    /// Tries to become the only scheduling engine. Lock is held until this connection is closed,
+    /// so standby engine takes over when connection of the leader drops.
+    pub fn try_lock_scheduler(&mut self) -> Result<bool, ErrorType> {
+        let row = self.client()?.query_one("SELECT pg_try_advisory_lock($1, $2)", &[&SCHEDULER_LOCK.0, &SCHEDULER_LOCK.1])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Marks epoch of engine that was shut down.
+    pub fn stop_engine(&mut self, epoch: i64) -> Result<(), ErrorType> {
+        engine_querry::mark_epoch_as_stopped(self.client()?, epoch)
+    }
+}
@@ -1,8 +1,13 @@
-use std::time::SystemTime;
+use std::{collections::HashSet, time::{Duration, SystemTime}};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use postgres::fallible_iterator::FallibleIterator;
 
 use crate::{
     database::{
         common::{Database, ErrorType},
+        repositories::engine::engine_querry,
         schema,
     },
     processing::job::JobType,
@@ -10,9 +15,10 @@ use crate::{
 
 /// Represents a task or an Task tree.
 /// Task is a single unit of work marked with status, timestamp and data and params.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
     /// Unique Id for task and state of the task.
+    #[allow(dead_code)]
     id: i64,
     /// Unique Id of task
     pub task_id: i64,
@@ -21,11 +27,91 @@ pub struct Task {
     /// Status of the task.
     pub status: schema::Status,
     /// Timestamp - unix
+    #[allow(dead_code)]
     pub timestamp: i64,
     /// output of given task. None if task hasnt been completed yet.
     pub data: Option<String>,
     /// params of given task.
     pub params: JobType,
+    /// How many times task was started.
+    pub attempt: i32,
+    /// Unix timestamp before which failed task won't be retried.
+    pub retry_at: Option<i64>,
+    /// Tasks with higher priority are claimed first.
+    pub priority: i32,
+    /// Permanently failed ancestor because of which task is blocked.
+    pub blocked_by: Option<i64>,
+    /// Fencing token of the claim that started the task. Worker has to present it to complete or fail the task.
+    pub claim_token: Option<i64>,
+    /// Unix timestamp of last lease renewal by worker running the task.
+    pub heartbeat: Option<i64>,
+    /// Why task failed, set on failed and dead tasks.
+    pub failure_kind: Option<schema::FailureKind>,
+    pub error_message: Option<String>,
+    /// Worker that ran the task (thread name and process id).
+    pub worker: Option<String>,
+    /// Epoch of engine that claimed the task, set on claimed and running tasks.
+    pub epoch: Option<i64>,
+}
+
+/// Reason of task failure recorded in the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub kind: schema::FailureKind,
+    pub message: String,
+    /// If false, task is marked as dead without further attempts.
+    pub retryable: bool,
+}
+
+impl Failure {
+    pub fn new(kind: schema::FailureKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into(), retryable: true }
+    }
+}
+
+/// How many times task can be started before it is marked as dead and how long to wait between attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: i32,
+    /// Delay after first failure, doubled after each next one.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Reads policy from `MAX_ATTEMPTS`, `RETRY_BACKOFF_SECS` and `RETRY_MAX_BACKOFF_SECS`, missing values are taken from default.
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|value| value.parse().ok())
+        }
+
+        let default = Self::default();
+
+        Self {
+            max_attempts: var("MAX_ATTEMPTS").unwrap_or(default.max_attempts),
+            backoff: var("RETRY_BACKOFF_SECS").map(Duration::from_secs).unwrap_or(default.backoff),
+            max_backoff: var("RETRY_MAX_BACKOFF_SECS").map(Duration::from_secs).unwrap_or(default.max_backoff),
+        }
+    }
+
+    /// Delay before next attempt of task that failed on its `attempt`-th run.
+    pub fn backoff_for(&self, attempt: i32) -> Duration {
+        let exponent = attempt.saturating_sub(1).clamp(0, 31) as u32;
+
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff)
+    }
 }
 
 pub struct InsertableTaskTree {
@@ -33,6 +119,8 @@ pub struct InsertableTaskTree {
     pub status: schema::Status,
     pub data: Option<String>,
     pub params: JobType,
+    /// Tasks with higher priority are claimed first. Parent tasks inherit priority of their children.
+    pub priority: i32,
 }
 
 pub struct InsertableTask {
@@ -40,6 +128,8 @@ pub struct InsertableTask {
     pub status: schema::Status,
     pub data: Option<String>,
     pub params: JobType,
+    /// Tasks with higher priority are claimed first. Ancestors of the task inherit its priority.
+    pub priority: i32,
 }
 
 impl InsertableTaskTree {
@@ -49,6 +139,7 @@ impl InsertableTaskTree {
             status: schema::Status::Completed,
             data: Some(data.to_string()),
             params: JobType::input(),
+            priority: 0,
         }
     }
 }
@@ -60,12 +151,16 @@ impl InsertableTask {
             status: schema::Status::Completed,
             data: Some(data.to_string()),
             params: JobType::input(),
+            priority: 0,
         }
     }
 }
 
 
-fn get_timestamp() -> i64 {
+/// Channel on which task events are sent.
+const TASK_EVENTS_CHANNEL: &str = "task_events";
+
+pub(crate) fn get_timestamp() -> i64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
@@ -73,269 +168,465 @@ fn get_timestamp() -> i64 {
 }
 
 mod task_querry {
-    use super::Task;
+    use super::{Failure, Task};
     use crate::{
-        database::{common::ErrorType, schema, repositories::task::get_timestamp},
-        processing::job::JobType,
+        database::{common::ErrorType, schema, repositories::task::{get_timestamp, RetryPolicy}},
     };
-    use log::info;
-    use postgres::{GenericClient};
+    use std::collections::HashMap;
+
+    use log::{info, warn};
+    use postgres::{GenericClient, Row};
+
+    fn task_from_row(row: &Row) -> Result<Task, ErrorType> {
+        let params: String = row.try_get("params")?;
+
+        Ok(Task {
+            id: row.try_get("id")?,
+            task_id: row.try_get("task_id")?,
+            parent_tasks: None,
+            status: row.try_get("status")?,
+            timestamp: row.try_get("timestamp")?,
+            data: row.try_get("data")?,
+            params: serde_json::from_str(&params)?,
+            attempt: row.try_get("attempt")?,
+            retry_at: row.try_get("retry_at")?,
+            // lease is kept in task_state only, rows of the journal have none
+            heartbeat: if row.columns().iter().any(|column| column.name() == "heartbeat") {
+                row.try_get("heartbeat")?
+            } else {
+                None
+            },
+            claim_token: row.try_get("claim_token")?,
+            blocked_by: row.try_get("blocked_by")?,
+            priority: row.try_get("priority")?,
+            failure_kind: row.try_get("failure_kind")?,
+            error_message: row.try_get("error_message")?,
+            worker: row.try_get("worker")?,
+            epoch: row.try_get("epoch")?,
+        })
+    }
+
+    /// Checks if write comes from worker holding the latest claim of the task.
+    pub fn check_claim_token(task: &Task, claim_token: i64) -> Result<(), ErrorType> {
+        if task.claim_token == Some(claim_token) {
+            Ok(())
+        } else {
+            warn!("Rejected write for task {} with outdated claim token {} (current {:?})", task.task_id, claim_token, task.claim_token);
+            Err(ErrorType::StaleClaim(task.task_id))
+        }
+    }
 
     pub fn get_parent_tasks(conn: &mut impl GenericClient, child_task_id: i64) -> Result<Vec<Task>, ErrorType> {
         // get all parent tasks 
         // it means: select all tasks that have parents with child_task_id is in parents table (sub select)
-        //TODO: fix this query it should select exactly one record for each parent task
         const QUERY: &str = r#"
-        WITH latest_tasks AS (                          
-            SELECT t.* FROM tasks t                                                    
-            INNER JOIN (
-                SELECT task_id, MAX(id) AS max_id
-                FROM tasks
-                GROUP BY task_id ) latest ON t.task_id = latest.task_id AND t.id = latest.max_id
-        )
-        SELECT t.id, t.task_id, status, timestamp, data, params FROM latest_tasks t WHERE t.task_id IN (SELECT parent_id FROM parents WHERE task_id = $1)
+        SELECT t.* FROM task_state t WHERE t.task_id IN (SELECT parent_id FROM parents WHERE task_id = $1)
+        ORDER BY t.task_id
         "#;
 
         let rows = conn.query(QUERY, &[&child_task_id])?;
 
-        let mut tasks = Vec::new();
-
-        for row in rows {
-            let id: i64 = row.try_get(0)?;
-            let task_id: i64 = row.try_get(1)?;
-            let status: schema::Status = row.try_get(2)?;
-            let timestamp: i64 = row.try_get(3)?;
-            let data: Option<String> = row.try_get(4)?;
-            let params: String = row.try_get(5)?;
-
-            let params: JobType = serde_json::from_str(&params)?;
-
-            tasks.push(Task {
-                id,
-                task_id,
-                parent_tasks: None,
-                status,
-                timestamp,
-                data,
-                params,
-            });
-        }
-
-        Ok(tasks)
+        rows.iter().map(task_from_row).collect()
     }
 
     pub fn get_runnable_tasks(conn: &mut impl GenericClient) -> Result<Vec<Task>, ErrorType> {
         // select tasks that have no parents, or ALL parents are completed
         const QUERRY: &str = r#"
-        WITH latest_tasks AS (                          
-            SELECT t.* FROM tasks t                                                    
-            INNER JOIN (
-                SELECT task_id, MAX(id) AS max_id
-                FROM tasks
-                GROUP BY task_id ) latest ON t.task_id = latest.task_id AND t.id = latest.max_id
-        )
-        SELECT * FROM latest_tasks WHERE task_id IN (                                                               
-        SELECT lt.task_id                                                                                                     
-        FROM latest_tasks lt
+        SELECT * FROM task_state WHERE task_id IN (
+        SELECT lt.task_id
+        FROM task_state lt
         LEFT JOIN parents p ON lt.task_id = p.task_id
-        LEFT JOIN latest_tasks lt2 ON p.parent_id = lt2.task_id
-        WHERE lt.status IN ('pending', 'failed')
+        LEFT JOIN task_state lt2 ON p.parent_id = lt2.task_id
+        WHERE lt.status IN ('pending', 'failed') AND (lt.retry_at IS NULL OR lt.retry_at <= $1)
         GROUP BY lt.task_id
-        HAVING ( COUNT(DISTINCT lt2.status) = 0 OR (COUNT(DISTINCT lt2.status) = 1 AND MAX(lt2.status) = 'completed' )));
+        HAVING ( COUNT(DISTINCT lt2.status) = 0 OR (COUNT(DISTINCT lt2.status) = 1 AND MAX(lt2.status) = 'completed' )))
+        ORDER BY priority DESC, task_id ASC;
         "#;
 
-        let rows = conn.query(QUERRY, &[])?;
+        let rows = conn.query(QUERRY, &[&get_timestamp()])?;
 
-        let mut tasks = Vec::new();
-
-        for row in rows {
-            let id: i64 = row.try_get(0)?;
-            let task_id: i64 = row.try_get(1)?;
-            let status: schema::Status = row.try_get(2)?;
-            let timestamp: i64 = row.try_get(3)?;
-            let data: Option<String> = row.try_get(4)?;
-            let params: String = row.try_get(5)?;
-
-            let params: JobType = serde_json::from_str(&params)?;
-
-            tasks.push(Task {
-                id,
-                task_id,
-                parent_tasks: None,
-                status,
-                timestamp,
-                data,
-                params,
-            });
-        }
-
-        Ok(tasks)
+        rows.iter().map(task_from_row).collect()
     }
 
     pub fn get_last_task_state(conn: &mut impl GenericClient, task_id: i64) -> Result<Task, ErrorType> {
-        const QUERY: &str = "SELECT id, task_id, status, timestamp, data, params FROM tasks WHERE task_id = $1 ORDER BY id DESC LIMIT 1";
+        const QUERY: &str = "SELECT * FROM task_state WHERE task_id = $1";
         
         let row = conn.query_one(QUERY, &[&task_id])?;
 
-        let id: i64 = row.try_get(0)?;
-        let task_id: i64 = row.try_get(1)?;
-        let status: schema::Status = row.try_get(2)?;
-        let timestamp: i64 = row.try_get(3)?;
-        let data: Option<String> = row.try_get(4)?;
-        let params: String = row.try_get(5)?;
-        let params: JobType = serde_json::from_str(&params)?;
-        
-        Ok(Task {
-            id,
-            task_id,
-            parent_tasks: None,
-            status,
-            timestamp,
-            data,
-            params,
-        })
+        task_from_row(&row)
     }
 
-    pub fn is_task_not_completed(conn: &mut impl GenericClient, task_id: i64) -> Result<bool, ErrorType> {
-        use schema::Status;
-        
-        let task = get_last_task_state(conn, task_id)?;
-    
-        match task.status {
-            Status::Pending|Status::Failed => Ok(true),
-            _ => Ok(false),
-        }
+    /// Current state of task for a write - its row is locked until end of transaction, so writes checking
+    /// the claim of the same task (e.g. completion and failure of expired lease) can't both pass the check.
+    pub fn lock_task_state(conn: &mut impl GenericClient, task_id: i64) -> Result<Task, ErrorType> {
+        const QUERY: &str = "SELECT * FROM task_state WHERE task_id = $1 FOR UPDATE";
+
+        let row = conn.query_one(QUERY, &[&task_id])?;
+
+        task_from_row(&row)
     }
 
     pub fn insert_status(conn: &mut impl GenericClient, task: &Task, status: schema::Status) -> Result<(), ErrorType> {
-        const QUERY: &str = "INSERT INTO tasks (task_id, status, timestamp, data, params) VALUES ($1, $2, $3, $4, $5)";
+        const QUERY: &str = "INSERT INTO tasks (task_id, status, timestamp, data, params, attempt, retry_at, claim_token, blocked_by, priority, failure_kind, error_message, worker, epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)";
 
         let timestamp = get_timestamp();
 
         info!("'{:?}' for task '{:?}'", status, task.task_id);
 
-        conn.execute(QUERY, &[&task.task_id, &status, &timestamp, &task.data, &serde_json::to_string(&task.params)?])?;
+        // failure reason is kept only until task is run again
+        let (failure_kind, error_message) = match status {
+            schema::Status::Failed | schema::Status::Dead | schema::Status::Pending => (task.failure_kind, task.error_message.as_ref()),
+            _ => (None, None),
+        };
+        // claim belongs to its engine only until task is finished or released
+        let epoch = match status {
+            schema::Status::Claimed | schema::Status::Running => task.epoch,
+            _ => None,
+        };
+
+        conn.execute(QUERY, &[&task.task_id, &status, &timestamp, &task.data, &serde_json::to_string(&task.params)?, &task.attempt, &task.retry_at, &task.claim_token, &task.blocked_by, &task.priority, &failure_kind, &error_message, &task.worker, &epoch])?;
 
         Ok(())
     }
 
-    pub fn mark_task_as_running(conn: &mut impl GenericClient, task: &Task) -> Result<(), ErrorType> {
-        let mut tx: postgres::Transaction = conn.transaction()?;
-        // check if task is runnable
-        if is_task_not_completed(&mut tx, task.task_id)? {
-            insert_status(&mut tx, task, schema::Status::Running)?;
-            tx.commit()?;
-            Ok(())
-        } else {
-            Err(ErrorType::TaskNotRunnable(task.task_id))
+    /// Claims up to `limit` runnable tasks of given kinds with new fencing tokens in single statement, highest priority first.
+    /// Rows of tasks claimed concurrently by other connections are locked and skipped, so each task is claimed only once.
+    /// Tasks of paused pipelines are not claimed. Claims are stamped with `epoch` of claiming engine.
+    pub fn claim_runnable_tasks(conn: &mut impl GenericClient, kinds: &[impl AsRef<str>], limit: Option<u32>, epoch: Option<i64>) -> Result<Vec<Task>, ErrorType> {
+        const QUERY: &str = r#"
+        WITH RECURSIVE paused AS (
+            SELECT task_id FROM paused_pipelines
+            UNION
+            SELECT p.parent_id FROM parents p INNER JOIN paused ON p.task_id = paused.task_id
+        ),
+        candidates AS (
+            SELECT s.* FROM task_state s
+            WHERE s.status IN ('pending', 'failed') AND (s.retry_at IS NULL OR s.retry_at <= $1)
+                AND s.kind = ANY($2)
+                AND NOT EXISTS (
+                    SELECT 1 FROM parents p INNER JOIN task_state ps ON ps.task_id = p.parent_id
+                    WHERE p.task_id = s.task_id AND ps.status <> 'completed')
+                AND s.task_id NOT IN (SELECT task_id FROM paused)
+            ORDER BY s.priority DESC, s.task_id ASC
+            LIMIT $3
+            FOR UPDATE OF s SKIP LOCKED
+        )
+        INSERT INTO tasks (task_id, status, timestamp, data, params, attempt, retry_at, claim_token, blocked_by, priority, epoch)
+        SELECT task_id, 'claimed', $1, data, params, attempt + 1, NULL, nextval('claim_token_seq'), blocked_by, priority, $4 FROM candidates
+        RETURNING *
+        "#;
+
+        let kinds = kinds.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        let limit = limit.map(i64::from);
+
+        let rows = conn.query(QUERY, &[&get_timestamp(), &kinds, &limit, &epoch])?;
+
+        let mut tasks = rows.iter().map(task_from_row).collect::<Result<Vec<_>, _>>()?;
+        tasks.sort_by_key(|task| (std::cmp::Reverse(task.priority), task.task_id));
+
+        for task in &tasks {
+            info!("'{:?}' for task '{:?}'", task.status, task.task_id);
         }
+
+        Ok(tasks)
     }
 
-    pub fn mark_task_as_failed(_conn: &mut impl GenericClient, task_id: i64) -> Result<(), ErrorType> {
-        let mut tx = _conn.transaction()?;
+    /// Parents of all given tasks in one query, grouped by child task id.
+    pub fn get_parents_of_tasks(conn: &mut impl GenericClient, task_ids: &[i64]) -> Result<HashMap<i64, Vec<Task>>, ErrorType> {
+        const QUERY: &str = r#"
+        SELECT p.task_id AS child_id, s.* FROM parents p
+        INNER JOIN task_state s ON s.task_id = p.parent_id
+        WHERE p.task_id = ANY($1)
+        ORDER BY p.task_id, p.parent_id
+        "#;
+
+        let rows = conn.query(QUERY, &[&task_ids])?;
+
+        let mut parents: HashMap<i64, Vec<Task>> = HashMap::new();
+        for row in &rows {
+            parents.entry(row.try_get("child_id")?).or_default().push(task_from_row(row)?);
+        }
+
+        Ok(parents)
+    }
+
+    pub fn mark_task_as_running(conn: &mut impl GenericClient, task_id: i64, claim_token: i64, worker: &str) -> Result<(), ErrorType> {
+        let mut tx = conn.transaction()?;
+
+        let mut task = lock_task_state(&mut tx, task_id)?;
 
-        let task = get_last_task_state(&mut tx, task_id)?;
-        insert_status(&mut tx, &task, schema::Status::Failed)?;
+        // only task claimed for worker can be started, it could be released in the meantime
+        if task.status != schema::Status::Claimed {
+            return Err(ErrorType::TaskNotRunnable(task_id));
+        }
+        check_claim_token(&task, claim_token)?;
 
+        task.worker = Some(worker.to_string());
+        insert_status(&mut tx, &task, schema::Status::Running)?;
         tx.commit()?;
 
         Ok(())
     }
 
-    pub fn search_for_timeouted(_conn: &mut impl GenericClient, _timeout: std::time::Duration) -> Result<Vec<Task>, ErrorType> {
+    /// Renews lease of running task. Returns false if task is not running with given claim anymore.
+    /// Heartbeat is written to task_state, journal is not changed.
+    pub fn renew_lease(conn: &mut impl GenericClient, task_id: i64, claim_token: i64) -> Result<bool, ErrorType> {
+        const QUERY: &str = r#"
+        UPDATE task_state SET heartbeat = $2
+        WHERE task_id = $1 AND status = 'running' AND claim_token = $3
+        "#;
+
+        let updated = conn.execute(QUERY, &[&task_id, &get_timestamp(), &claim_token])?;
+
+        Ok(updated > 0)
+    }
+
+    /// Tasks claimed before `timeout`. Claims of `held_kinds` and of paused pipelines are kept, workers hold them while paused.
+    pub fn search_for_stale_claims(conn: &mut impl GenericClient, timeout: std::time::Duration, held_kinds: &[impl AsRef<str>]) -> Result<Vec<Task>, ErrorType> {
         const QUERRY: &str = r#"
-        WITH latest_tasks AS (                          
-            SELECT t.* FROM tasks t                                                    
-            INNER JOIN (
-                SELECT task_id, MAX(id) AS max_id
-                FROM tasks
-                GROUP BY task_id ) latest ON t.task_id = latest.task_id AND t.id = latest.max_id
+        WITH RECURSIVE paused AS (
+            SELECT task_id FROM paused_pipelines
+            UNION
+            SELECT p.parent_id FROM parents p INNER JOIN paused ON p.task_id = paused.task_id
         )
-        SELECT * FROM latest_tasks WHERE status = 'running' and timestamp < $1
+        SELECT * FROM task_state
+        WHERE status = 'claimed' and timestamp < $1
+            AND kind <> ALL($2)
+            AND task_id NOT IN (SELECT task_id FROM paused)
+        FOR UPDATE OF task_state
         "#;
 
-        let timeout_time = get_timestamp() - _timeout.as_secs() as i64;
+        let timeout_time = get_timestamp() - timeout.as_secs() as i64;
+        let held_kinds = held_kinds.iter().map(|kind| kind.as_ref()).collect::<Vec<_>>();
 
-        let rows = _conn.query(QUERRY, &[&timeout_time])?;
+        let rows = conn.query(QUERRY, &[&timeout_time, &held_kinds])?;
+
+        rows.iter().map(task_from_row).collect()
+    }
 
-        let mut tasks = Vec::new();
+    /// Fails claimed or running task. Failure already recorded for the same claim is accepted without effect.
+    pub fn mark_task_as_failed(_conn: &mut impl GenericClient, task_id: i64, claim_token: i64, failure: &Failure, policy: &RetryPolicy) -> Result<(), ErrorType> {
+        let mut tx = _conn.transaction()?;
 
-        for row in rows {
-            let id: i64 = row.try_get(0)?;
-            let task_id: i64 = row.try_get(1)?;
-            let status: schema::Status = row.try_get(2)?;
-            let timestamp: i64 = row.try_get(3)?;
-            let data: Option<String> = row.try_get(4)?;
-            let params: String = row.try_get(5)?;
-
-            let params: JobType = serde_json::from_str(&params)?;
-
-            tasks.push(Task {
-                id,
-                task_id,
-                parent_tasks: None,
-                status,
-                timestamp,
-                data,
-                params,
-            });
+        let task = lock_task_state(&mut tx, task_id)?;
+
+        match task.status {
+            schema::Status::Claimed | schema::Status::Running => {}
+            // result replayed from worker outbox was already recorded
+            schema::Status::Failed | schema::Status::Dead if task.claim_token == Some(claim_token) => return Ok(()),
+            schema::Status::Cancelled => return Err(ErrorType::TaskCancelled(task_id)),
+            _ => return Err(ErrorType::StaleClaim(task_id)),
         }
+        check_claim_token(&task, claim_token)?;
 
-        Ok(tasks)
+        insert_failed_status(&mut tx, task, failure, policy)?;
+
+        tx.commit()?;
+
+        Ok(())
     }
 
-    pub fn get_all_tasks(conn: &mut postgres::Client) -> Result<Vec<Task>, ErrorType> {
+    /// Inserts `failed` status with time of next retry, or `dead` if task ran out of attempts or failure is permanent.
+    pub fn insert_failed_status(conn: &mut impl GenericClient, mut task: Task, failure: &Failure, policy: &RetryPolicy) -> Result<(), ErrorType> {
+        warn!("Task {} failed on attempt {} ({:?}): {}", task.task_id, task.attempt, failure.kind, failure.message);
+        task.failure_kind = Some(failure.kind);
+        task.error_message = Some(failure.message.clone());
+
+        if !failure.retryable || task.attempt >= policy.max_attempts {
+            if failure.retryable {
+                warn!("Task {} failed {} times, marking as dead", task.task_id, task.attempt);
+            } else {
+                warn!("Task {} failed permanently, marking as dead", task.task_id);
+            }
+            task.retry_at = None;
+            insert_status(conn, &task, schema::Status::Dead)?;
+            block_descendants(conn, task.task_id)
+        } else {
+            task.retry_at = Some(get_timestamp() + policy.backoff_for(task.attempt).as_secs() as i64);
+            insert_status(conn, &task, schema::Status::Failed)
+        }
+    }
+
+    /// Moves waiting descendants of permanently failed (or cancelled) task to `blocked`, remembering which ancestor blocked them.
+    pub fn block_descendants(conn: &mut impl GenericClient, task_id: i64) -> Result<(), ErrorType> {
+        for descendant_id in get_descendant_ids(conn, task_id)? {
+            let mut descendant = get_last_task_state(conn, descendant_id)?;
+
+            if matches!(descendant.status, schema::Status::Pending | schema::Status::Failed) {
+                descendant.blocked_by = Some(task_id);
+                insert_status(conn, &descendant, schema::Status::Blocked)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns tasks blocked by given task back to pending, after it completed.
+    /// Task that has another permanently failed or cancelled ancestor is blocked by that one instead.
+    pub fn unblock_descendants(conn: &mut impl GenericClient, task_id: i64) -> Result<(), ErrorType> {
+        for descendant_id in get_descendant_ids(conn, task_id)? {
+            let mut descendant = get_last_task_state(conn, descendant_id)?;
+
+            if descendant.status != schema::Status::Blocked || descendant.blocked_by != Some(task_id) {
+                continue;
+            }
+
+            descendant.blocked_by = find_dead_ancestor(conn, descendant_id)?;
+
+            match descendant.blocked_by {
+                Some(_) => insert_status(conn, &descendant, schema::Status::Blocked)?,
+                None => insert_status(conn, &descendant, schema::Status::Pending)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn find_dead_ancestor(conn: &mut impl GenericClient, task_id: i64) -> Result<Option<i64>, ErrorType> {
         const QUERY: &str = r#"
-        SELECT t.* FROM tasks t                                                    
-            INNER JOIN (
-                SELECT task_id, MAX(id) AS max_id
-                FROM tasks
-                GROUP BY task_id ) latest ON t.task_id = latest.task_id AND t.id = latest.max_id
+        WITH RECURSIVE ancestors AS (
+            SELECT parent_id FROM parents WHERE task_id = $1
+            UNION
+            SELECT p.parent_id FROM parents p INNER JOIN ancestors a ON p.task_id = a.parent_id
+        )
+        SELECT t.task_id FROM task_state t
+        WHERE t.task_id IN (SELECT parent_id FROM ancestors)
+            AND t.status IN ('dead', 'cancelled')
+        LIMIT 1
         "#;
 
-        let rows = conn.query(QUERY, &[])?;
+        let row = conn.query_opt(QUERY, &[&task_id])?;
 
-        let mut tasks = Vec::new();
+        Ok(match row {
+            Some(row) => Some(row.try_get(0)?),
+            None => None,
+        })
+    }
+
+    /// Raises priority of waiting ancestors of the task, so its inputs are processed first.
+    pub fn raise_ancestors_priority(conn: &mut impl GenericClient, task_id: i64, priority: i32) -> Result<(), ErrorType> {
+        const QUERY: &str = r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT parent_id FROM parents WHERE task_id = $1
+            UNION
+            SELECT p.parent_id FROM parents p INNER JOIN ancestors a ON p.task_id = a.parent_id
+        )
+        SELECT parent_id FROM ancestors
+        "#;
+
+        let rows = conn.query(QUERY, &[&task_id])?;
 
         for row in rows {
-            let id: i64 = row.try_get(0)?;
-            let task_id: i64 = row.try_get(1)?;
-            let status: schema::Status = row.try_get(2)?;
-            let timestamp: i64 = row.try_get(3)?;
-            let data: Option<String> = row.try_get(4)?;
-            let params: String = row.try_get(5)?;
-
-            let params: JobType = serde_json::from_str(&params)?;
-
-            tasks.push(Task {
-                id,
-                task_id,
-                parent_tasks: None,
-                status,
-                timestamp,
-                data,
-                params,
-            });
+            let mut ancestor = get_last_task_state(conn, row.try_get(0)?)?;
+
+            if ancestor.priority < priority && matches!(ancestor.status, schema::Status::Pending | schema::Status::Failed | schema::Status::Blocked) {
+                let status = ancestor.status;
+                ancestor.priority = priority;
+                insert_status(conn, &ancestor, status)?;
+            }
         }
 
-        Ok(tasks)
+        Ok(())
+    }
+
+    /// Epochs of engines that have some claimed or running tasks.
+    pub fn get_epochs_with_claims(conn: &mut impl GenericClient) -> Result<Vec<i64>, ErrorType> {
+        const QUERY: &str = r#"
+        SELECT DISTINCT epoch FROM task_state WHERE status IN ('claimed', 'running') AND epoch IS NOT NULL
+        "#;
+
+        let rows = conn.query(QUERY, &[])?;
+
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    pub fn get_claims_of_epoch(conn: &mut impl GenericClient, epoch: i64) -> Result<Vec<Task>, ErrorType> {
+        const QUERY: &str = r#"
+        SELECT * FROM task_state WHERE status IN ('claimed', 'running') AND epoch = $1
+        FOR UPDATE
+        "#;
+
+        let rows = conn.query(QUERY, &[&epoch])?;
+
+        rows.iter().map(task_from_row).collect()
+    }
+
+    /// Wakes up scheduler, e.g. when some tasks may have become claimable again.
+    pub fn notify_task_event(conn: &mut impl GenericClient) -> Result<(), ErrorType> {
+        conn.execute("SELECT pg_notify($1, '')", &[&super::TASK_EVENTS_CHANNEL])?;
+
+        Ok(())
+    }
+
+    /// Ids of all tasks that depend (directly or not) on given task.
+    pub fn get_descendant_ids(conn: &mut impl GenericClient, task_id: i64) -> Result<Vec<i64>, ErrorType> {
+        const QUERY: &str = r#"
+        WITH RECURSIVE descendants AS (
+            SELECT task_id FROM parents WHERE parent_id = $1
+            UNION
+            SELECT p.task_id FROM parents p INNER JOIN descendants d ON p.parent_id = d.task_id
+        )
+        SELECT task_id FROM descendants
+        "#;
+
+        let rows = conn.query(QUERY, &[&task_id])?;
+
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    pub fn get_dead_tasks(conn: &mut impl GenericClient) -> Result<Vec<Task>, ErrorType> {
+        const QUERY: &str = r#"
+        SELECT * FROM task_state WHERE status = 'dead'
+        "#;
+
+        let rows = conn.query(QUERY, &[])?;
+
+        rows.iter().map(task_from_row).collect()
+    }
+
+    /// Running tasks which lease expired. Their rows are locked, task completed meanwhile is not returned.
+    pub fn search_for_timeouted(_conn: &mut impl GenericClient, _timeout: std::time::Duration) -> Result<Vec<Task>, ErrorType> {
+        const QUERRY: &str = r#"
+        SELECT * FROM task_state WHERE status = 'running' and COALESCE(heartbeat, timestamp) < $1
+        FOR UPDATE
+        "#;
+
+        // task is timeouted when its lease (last heartbeat, or start if there was none) expired
+        let timeout_time = get_timestamp() - _timeout.as_secs() as i64;
+
+        let rows = _conn.query(QUERRY, &[&timeout_time])?;
+
+        rows.iter().map(task_from_row).collect()
+    }
+
+    pub fn get_all_tasks(conn: &mut postgres::Client) -> Result<Vec<Task>, ErrorType> {
+        const QUERY: &str = r#"
+        SELECT * FROM task_state
+        "#;
+
+        let rows = conn.query(QUERY, &[])?;
+
+        rows.iter().map(task_from_row).collect()
     }
 }
 
 impl Database {
     pub fn insert_new_task_tree(&mut self, task: &InsertableTaskTree) -> Result<(), ErrorType> {
-        const QUERY: &str = "INSERT INTO tasks (task_id, status, timestamp, data, params) VALUES ($1, $2, $3, $4, $5)";
+        const QUERY: &str = "INSERT INTO tasks (task_id, status, timestamp, data, params, priority) VALUES ($1, $2, $3, $4, $5, $6)";
         const QUERY2: &str = "INSERT INTO parents (task_id, parent_id) VALUES ($1, $2)";
 
         fn insert_task(
             tx: &mut postgres::Transaction,
             task: &InsertableTaskTree,
             timestamp: i64,
+            inherited_priority: i32,
         ) -> Result<i64, ErrorType> {
             // get next free task_id
             let task_id = tx.query_one("SELECT nextval('task_id_seq')", &[])?;
             let task_id: i64 = task_id.try_get(0)?;
 
+            let priority = task.priority.max(inherited_priority);
+
             // insert task
             tx.execute(
                 QUERY,
@@ -345,6 +636,7 @@ impl Database {
                     &timestamp,
                     &task.data,
                     &serde_json::to_string(&task.params)?,
+                    &priority,
                 ],
             )?;
 
@@ -352,7 +644,7 @@ impl Database {
 
             // insert parents
             for parent in &task.parent_tasks {
-                parent_ids.push(insert_task(tx, parent, timestamp)?);
+                parent_ids.push(insert_task(tx, parent, timestamp, priority)?);
             }
 
             // insert parent relations
@@ -365,15 +657,15 @@ impl Database {
 
         let timestamp = get_timestamp();
 
-        let mut tx = self.conn.transaction()?;
-        insert_task(&mut tx, task, timestamp)?;
+        let mut tx = self.client()?.transaction()?;
+        insert_task(&mut tx, task, timestamp, i32::MIN)?;
         tx.commit()?;
 
         Ok(())
     }
 
     pub fn insert_new_task(&mut self, task: &InsertableTask) -> Result<(), ErrorType> {
-        const QUERY: &str = "INSERT INTO tasks (task_id, status, timestamp, data, params) VALUES ($1, $2, $3, $4, $5)";
+        const QUERY: &str = "INSERT INTO tasks (task_id, status, timestamp, data, params, priority) VALUES ($1, $2, $3, $4, $5, $6)";
         const QUERY2: &str = "INSERT INTO parents (task_id, parent_id) VALUES ($1, $2)";
 
         fn insert_task(
@@ -394,6 +686,7 @@ impl Database {
                     &timestamp,
                     &task.data,
                     &serde_json::to_string(&task.params)?,
+                    &task.priority,
                 ],
             )?;
 
@@ -402,12 +695,14 @@ impl Database {
                 tx.execute(QUERY2, &[&task_id, parent_id])?;
             }
 
+            task_querry::raise_ancestors_priority(tx, task_id, task.priority)?;
+
             Ok(task_id)
         }
 
         let timestamp = get_timestamp();
 
-        let mut tx = self.conn.transaction()?;
+        let mut tx = self.client()?.transaction()?;
         insert_task(&mut tx, task, timestamp)?;
         tx.commit()?;
 
@@ -416,55 +711,255 @@ impl Database {
 
     #[allow(dead_code)]
     pub fn get_runnable_tasks(&mut self) -> Result<Vec<Task>, ErrorType> {
-        task_querry::get_runnable_tasks(&mut self.conn)
+        task_querry::get_runnable_tasks(self.client()?)
     }
 
     #[allow(dead_code)]
     pub fn get_last_task_state(&mut self, task_id: i64) -> Result<Task, ErrorType> {
-        task_querry::get_last_task_state(&mut self.conn, task_id)
+        task_querry::get_last_task_state(self.client()?, task_id)
     }
 
     #[allow(dead_code)]
     pub fn get_parent_tasks(&mut self, task_id: i64) -> Result<Vec<Task>, ErrorType> {
-        task_querry::get_parent_tasks(&mut self.conn, task_id)
+        task_querry::get_parent_tasks(self.client()?, task_id)
     }
 
     pub fn get_all_tasks(&mut self) -> Result<Vec<Task>, ErrorType> {
-        task_querry::get_all_tasks(&mut self.conn)
+        task_querry::get_all_tasks(self.client()?)
     }
 
-    pub fn mark_task_as_completed(&mut self, task_id: i64, out: &str) -> Result<(), ErrorType> {
-        let mut tx = self.conn.transaction()?;
+    /// Subscribes connection to events sent when task is inserted, failed or completed (see `V9__task_events.sql`).
+    pub fn listen_for_task_events(&mut self) -> Result<(), ErrorType> {
+        self.client()?.batch_execute(&format!("LISTEN {}", TASK_EVENTS_CHANNEL))?;
 
-        let mut task = task_querry::get_last_task_state(&mut tx, task_id)?;
-        task.data = Some(out.to_string());
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for task events, returns false if none arrived.
+    /// All pending events are consumed, so burst of changes wakes caller once.
+    pub fn wait_for_task_events(&mut self, timeout: Duration) -> Result<bool, ErrorType> {
+        let mut notifications = self.client()?.notifications();
 
-        if task.status != schema::Status::Running {
-            return Err(ErrorType::Other);
+        if notifications.timeout_iter(timeout).next()?.is_none() {
+            return Ok(false);
         }
 
+        let mut pending = notifications.iter();
+        while pending.next()?.is_some() {}
+
+        Ok(true)
+    }
+
+    /// Records output of running task. Completion already recorded for the same claim is accepted without effect,
+    /// so result replayed from worker outbox is recorded once.
+    pub fn mark_task_as_completed(&mut self, task_id: i64, claim_token: i64, out: &str) -> Result<(), ErrorType> {
+        let mut tx = self.client()?.transaction()?;
+
+        let mut task = task_querry::lock_task_state(&mut tx, task_id)?;
+
+        match task.status {
+            schema::Status::Running => {}
+            // result replayed from worker outbox was already recorded
+            schema::Status::Completed if task.claim_token == Some(claim_token) && task.data.as_deref() == Some(out) => return Ok(()),
+            schema::Status::Cancelled => return Err(ErrorType::TaskCancelled(task_id)),
+            _ => {
+                warn!("Rejected completion of task {} with status {:?}", task_id, task.status);
+                return Err(ErrorType::StaleClaim(task_id));
+            }
+        }
+        task_querry::check_claim_token(&task, claim_token)?;
+
+        task.data = Some(out.to_string());
         task_querry::insert_status(&mut tx, &task, schema::Status::Completed)?;
+        task_querry::unblock_descendants(&mut tx, task_id)?;
 
         tx.commit()?;
 
         Ok(())
     }
 
-    pub fn mark_task_as_failed(&mut self, task_id: i64) -> Result<(), ErrorType> {
-        task_querry::mark_task_as_failed(&mut self.conn, task_id)?;
+    /// Marks claimed task as running, called by worker when it starts processing it.
+    pub fn mark_task_as_running(&mut self, task_id: i64, claim_token: i64, worker: &str) -> Result<(), ErrorType> {
+        task_querry::mark_task_as_running(self.client()?, task_id, claim_token, worker)
+    }
+
+    pub fn renew_lease(&mut self, task_id: i64, claim_token: i64) -> Result<bool, ErrorType> {
+        task_querry::renew_lease(self.client()?, task_id, claim_token)
+    }
+
+    pub fn mark_task_as_failed(&mut self, task_id: i64, claim_token: i64, failure: Failure) -> Result<(), ErrorType> {
+        let policy = self.retry_policy;
+        task_querry::mark_task_as_failed(self.client()?, task_id, claim_token, &failure, &policy)?;
 
         Ok(())
     }
 
+    /// Marks completed task as failed because its output can't be loaded anymore, so it will be run again.
+    /// `completed_by` is claim that produced the lost output - output of newer run of the task is kept.
+    pub fn mark_output_as_lost(&mut self, task_id: i64, completed_by: Option<i64>) -> Result<(), ErrorType> {
+        let policy = self.retry_policy;
+        let mut tx = self.client()?.transaction()?;
+
+        let task = task_querry::lock_task_state(&mut tx, task_id)?;
+
+        if task.status != schema::Status::Completed {
+            return Err(ErrorType::TaskNotRunnable(task_id));
+        }
+        if task.claim_token != completed_by {
+            return Err(ErrorType::StaleClaim(task_id));
+        }
+
+        let failure = Failure::new(schema::FailureKind::OutputLost, format!("output {:?} can't be loaded", task.data));
+        task_querry::insert_failed_status(&mut tx, task, &failure, &policy)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Cancels task and, if `cascade` is set, every task that depends on it. Otherwise tasks depending on it
+    /// can never run, so they are blocked by it. Tasks that are already completed, dead or cancelled are left untouched.
+    /// Returns number of cancelled tasks.
+    pub fn cancel_task(&mut self, task_id: i64, cascade: bool) -> Result<u32, ErrorType> {
+        let mut tx = self.client()?.transaction()?;
+
+        let mut task_ids = vec![task_id];
+        if cascade {
+            task_ids.extend(task_querry::get_descendant_ids(&mut tx, task_id)?);
+        }
+
+        let mut cancelled = 0;
+
+        for task_id in task_ids {
+            let task = task_querry::lock_task_state(&mut tx, task_id)?;
+
+            match task.status {
+                schema::Status::Completed | schema::Status::Dead | schema::Status::Cancelled => {}
+                _ => {
+                    task_querry::insert_status(&mut tx, &task, schema::Status::Cancelled)?;
+                    cancelled += 1;
+                }
+            }
+        }
+
+        if !cascade {
+            task_querry::block_descendants(&mut tx, task_id)?;
+        }
+
+        tx.commit()?;
+
+        Ok(cancelled)
+    }
+
+    /// Pauses pipeline producing given task - the task and all tasks it depends on are not claimed until resumed.
+    /// Tasks of the pipeline waiting in worker queues are given back by worker threads, running tasks are finished.
+    pub fn pause_pipeline(&mut self, task_id: i64) -> Result<(), ErrorType> {
+        let mut tx = self.client()?.transaction()?;
+
+        // fails if task doesn't exist
+        task_querry::get_last_task_state(&mut tx, task_id)?;
+
+        tx.execute("INSERT INTO paused_pipelines (task_id) VALUES ($1) ON CONFLICT DO NOTHING", &[&task_id])?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn resume_pipeline(&mut self, task_id: i64) -> Result<(), ErrorType> {
+        self.client()?.execute("DELETE FROM paused_pipelines WHERE task_id = $1", &[&task_id])?;
+
+        task_querry::notify_task_event(self.client()?)
+    }
+
+    /// Checks if task belongs to a paused pipeline - it is output of the pipeline or the output depends on it.
+    pub fn is_task_paused(&mut self, task_id: i64) -> Result<bool, ErrorType> {
+        const QUERY: &str = r#"
+        WITH RECURSIVE paused AS (
+            SELECT task_id FROM paused_pipelines
+            UNION
+            SELECT p.parent_id FROM parents p INNER JOIN paused ON p.task_id = paused.task_id
+        )
+        SELECT EXISTS (SELECT 1 FROM paused WHERE task_id = $1)
+        "#;
+
+        let row = self.client()?.query_one(QUERY, &[&task_id])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Output tasks of paused pipelines.
+    pub fn get_paused_pipelines(&mut self) -> Result<Vec<i64>, ErrorType> {
+        let rows = self.client()?.query("SELECT task_id FROM paused_pipelines ORDER BY task_id", &[])?;
+
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Pauses worker type - its tasks are not claimed and its threads keep queued tasks until resumed.
+    pub fn pause_worker(&mut self, name: &str) -> Result<(), ErrorType> {
+        self.client()?.execute("INSERT INTO paused_workers (name) VALUES ($1) ON CONFLICT DO NOTHING", &[&name])?;
+
+        Ok(())
+    }
+
+    pub fn resume_worker(&mut self, name: &str) -> Result<(), ErrorType> {
+        self.client()?.execute("DELETE FROM paused_workers WHERE name = $1", &[&name])?;
+
+        task_querry::notify_task_event(self.client()?)
+    }
+
+    pub fn get_paused_workers(&mut self) -> Result<HashSet<String>, ErrorType> {
+        let rows = self.client()?.query("SELECT name FROM paused_workers", &[])?;
+
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    pub fn is_worker_paused(&mut self, name: &str) -> Result<bool, ErrorType> {
+        let row = self.client()?.query_one("SELECT EXISTS (SELECT 1 FROM paused_workers WHERE name = $1)", &[&name])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Gives dead task new set of attempts. Its blocked descendants are unblocked when it completes.
+    pub fn retry_task(&mut self, task_id: i64) -> Result<(), ErrorType> {
+        let mut tx = self.client()?.transaction()?;
+
+        let task = task_querry::lock_task_state(&mut tx, task_id)?;
+
+        if task.status != schema::Status::Dead {
+            return Err(ErrorType::TaskNotRunnable(task_id));
+        }
+
+        let task = Task {
+            attempt: 0,
+            retry_at: None,
+            ..task
+        };
+        task_querry::insert_status(&mut tx, &task, schema::Status::Pending)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Tasks that ran out of attempts and won't be retried.
+    pub fn get_dead_tasks(&mut self) -> Result<Vec<Task>, ErrorType> {
+        task_querry::get_dead_tasks(self.client()?)
+    }
+
     pub fn mark_as_failed_timeouted(&mut self, timeout: std::time::Duration) -> Result<u32, ErrorType> {
-        let mut tx = self.conn.transaction()?;
+        let policy = self.retry_policy;
+        let mut tx = self.client()?.transaction()?;
 
         let tasks = task_querry::search_for_timeouted(&mut tx, timeout)?;
 
         let timeouted_tasks = tasks.len();
 
+        let now = get_timestamp();
         for task in tasks {
-            task_querry::insert_status(&mut tx, &task, schema::Status::Failed)?;
+            let last_seen = task.heartbeat.unwrap_or(task.timestamp);
+            let failure = Failure::new(schema::FailureKind::Timeout, format!("lease expired, worker last seen {} s ago", now - last_seen));
+            task_querry::insert_failed_status(&mut tx, task, &failure, &policy)?;
         }
 
         tx.commit()?;
@@ -472,50 +967,154 @@ impl Database {
         Ok(timeouted_tasks as u32)
     }
 
-    pub fn claim_runnable_tasks<WorkerJobType: TryFrom<JobType> + Copy>(
-        &mut self,
-        limit: Option<u32>,
-    ) -> Result<Vec<Task>, ErrorType> {
-        let mut tx = self.transaction()?;
-
-        let tasks = task_querry::get_runnable_tasks(&mut tx)?;
-
-        let tasks = tasks
-            .iter()
-            .filter_map(|task| {
-                task.params
-                .try_into()
-                .ok()
-                .map(|_: WorkerJobType| Task {
-                    id: task.id,
-                    task_id: task.task_id,
-                    parent_tasks: Some(task_querry::get_parent_tasks(&mut tx, task.task_id).unwrap()),
-                    status: task.status,
-                    timestamp: task.timestamp,
-                    data: task.data.clone(),
-                    params: task.params,
-                })
-            })
-            .take(limit.unwrap_or(std::u32::MAX) as usize)
-            .collect::<Vec<_>>();
-
-        // claim tasks
-        for task in &tasks {
-            match task_querry::mark_task_as_running(&mut tx, task) {
-                Ok(_) => {},
-                Err(_) => println!("Task {:?} for worker not runnable ({:?})", task.task_id, task.status),
+    /// Returns tasks claimed longer than `timeout` ago back to pending.
+    /// Claim that was never started by worker is not counted as an attempt.
+    /// Claims of `held_kinds` (of paused workers) and of paused pipelines are kept in worker queues.
+    pub fn release_stale_claims(&mut self, timeout: std::time::Duration, held_kinds: &[impl AsRef<str>]) -> Result<u32, ErrorType> {
+        let mut tx = self.client()?.transaction()?;
+
+        let tasks = task_querry::search_for_stale_claims(&mut tx, timeout, held_kinds)?;
+
+        let released_tasks = tasks.len();
+
+        for task in tasks {
+            let task = Task {
+                attempt: task.attempt - 1,
+                ..task
+            };
+            task_querry::insert_status(&mut tx, &task, schema::Status::Pending)?;
+        }
+
+        tx.commit()?;
+
+        Ok(released_tasks as u32)
+    }
+
+    /// Returns claimed task that was never started back to pending, without counting it as an attempt.
+    pub fn release_claim(&mut self, task_id: i64, claim_token: i64) -> Result<(), ErrorType> {
+        let mut tx = self.client()?.transaction()?;
+
+        let task = task_querry::lock_task_state(&mut tx, task_id)?;
+
+        match task.status {
+            schema::Status::Claimed => {}
+            schema::Status::Cancelled => return Err(ErrorType::TaskCancelled(task_id)),
+            _ => return Err(ErrorType::StaleClaim(task_id)),
+        }
+        task_querry::check_claim_token(&task, claim_token)?;
+
+        let task = Task {
+            attempt: task.attempt - 1,
+            ..task
+        };
+        task_querry::insert_status(&mut tx, &task, schema::Status::Pending)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Recovers tasks claimed by engines that are no longer alive (their epoch lock is free).
+    /// Claimed tasks are returned to pending without counting an attempt, running tasks are failed.
+    /// Returns number of recovered tasks.
+    pub fn fail_over_dead_engines(&mut self) -> Result<u32, ErrorType> {
+        let policy = self.retry_policy;
+        let mut recovered = 0;
+
+        for epoch in task_querry::get_epochs_with_claims(self.client()?)? {
+            // own epoch is alive even when its lock was lost with the connection for a moment
+            if Some(epoch) == self.epoch {
+                continue;
             }
+
+            let mut tx = self.client()?.transaction()?;
+
+            // lock is held until end of transaction, so engine can't be failed over twice concurrently
+            if !engine_querry::try_lock_epoch(&mut tx, epoch)? {
+                continue;
+            }
+
+            let failure = Failure::new(schema::FailureKind::EngineDied, format!("engine of epoch {} is no longer running", epoch));
+
+            for task in task_querry::get_claims_of_epoch(&mut tx, epoch)? {
+                if task.status == schema::Status::Claimed {
+                    let task = Task {
+                        attempt: task.attempt - 1,
+                        failure_kind: Some(failure.kind),
+                        error_message: Some(failure.message.clone()),
+                        ..task
+                    };
+                    task_querry::insert_status(&mut tx, &task, schema::Status::Pending)?;
+                } else {
+                    task_querry::insert_failed_status(&mut tx, task, &failure, &policy)?;
+                }
+                recovered += 1;
+            }
+
+            engine_querry::mark_epoch_as_stopped(&mut tx, epoch)?;
+
+            tx.commit()?;
+        }
+
+        Ok(recovered)
+    }
+
+    /// Returns task that was queued for or running in worker thread which died back to pending right away.
+    /// Task that was only queued is not counted as an attempt, running task dies if it was its last attempt.
+    pub fn requeue_lost_task(&mut self, task_id: i64, claim_token: i64, worker: &str) -> Result<(), ErrorType> {
+        let policy = self.retry_policy;
+        let mut tx = self.client()?.transaction()?;
+
+        let task = task_querry::lock_task_state(&mut tx, task_id)?;
+
+        match task.status {
+            schema::Status::Claimed | schema::Status::Running => {}
+            schema::Status::Cancelled => return Err(ErrorType::TaskCancelled(task_id)),
+            _ => return Err(ErrorType::StaleClaim(task_id)),
+        }
+        task_querry::check_claim_token(&task, claim_token)?;
+
+        let failure = Failure::new(schema::FailureKind::WorkerDied, format!("worker {} died", worker));
+
+        if task.status == schema::Status::Running && task.attempt >= policy.max_attempts {
+            task_querry::insert_failed_status(&mut tx, task, &failure, &policy)?;
+        } else {
+            let task = Task {
+                attempt: if task.status == schema::Status::Claimed { task.attempt - 1 } else { task.attempt },
+                retry_at: None,
+                failure_kind: Some(failure.kind),
+                error_message: Some(failure.message),
+                ..task
+            };
+            task_querry::insert_status(&mut tx, &task, schema::Status::Pending)?;
         }
 
         tx.commit()?;
 
-        Ok(tasks)
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn claim_all_runnable_tasks<WorkerJobType: TryFrom<JobType> + Copy>(
+    /// Claims runnable tasks which job kind is one of `kinds`, together with their parents.
+    /// Returns only tasks that were actually claimed.
+    pub fn claim_runnable_tasks_of_kinds(
         &mut self,
+        kinds: &[impl AsRef<str>],
+        limit: Option<u32>,
     ) -> Result<Vec<Task>, ErrorType> {
-        self.claim_runnable_tasks::<WorkerJobType>(None)
+        let epoch = self.epoch;
+        let mut tx = self.client()?.transaction()?;
+
+        let mut tasks = task_querry::claim_runnable_tasks(&mut tx, kinds, limit, epoch)?;
+
+        let task_ids = tasks.iter().map(|task| task.task_id).collect::<Vec<_>>();
+        let mut parents = task_querry::get_parents_of_tasks(&mut tx, &task_ids)?;
+
+        for task in &mut tasks {
+            task.parent_tasks = Some(parents.remove(&task.task_id).unwrap_or_default());
+        }
+
+        tx.commit()?;
+
+        Ok(tasks)
     }
 }   
@@ -1,18 +1,64 @@
 use postgres_derive::{FromSql, ToSql};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, FromSql, ToSql)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, FromSql, ToSql, Serialize, Deserialize)]
 #[postgres(name = "status_type")]
 pub enum Status {
     #[postgres(name = "pending")]
     Pending,
+    /// Task was sent to worker, but worker hasn't started it yet
+    #[postgres(name = "claimed")]
+    Claimed,
     #[postgres(name = "running")]
     Running,
     #[postgres(name = "completed")]
     Completed,
     #[postgres(name = "failed")]
     Failed,
+    /// Task failed too many times and won't be retried
+    #[postgres(name = "dead")]
+    Dead,
+    #[postgres(name = "cancelled")]
+    Cancelled,
+    /// Some ancestor of the task is dead, so task can't be run until it is retried
+    #[postgres(name = "blocked")]
+    Blocked,
 }
 
+/// Reason why task failed
+#[derive(Debug, PartialEq, Eq, Clone, Copy, FromSql, ToSql, Serialize, Deserialize)]
+#[postgres(name = "failure_kind_type")]
+pub enum FailureKind {
+    /// Worker was unable to process the job
+    #[postgres(name = "process_error")]
+    ProcessError,
+    /// Output of some parent couldn't be loaded
+    #[postgres(name = "missing_input")]
+    MissingInput,
+    /// Worker stopped renewing lease of the task
+    #[postgres(name = "timeout")]
+    Timeout,
+    /// Task was completed, but its output was lost afterwards
+    #[postgres(name = "output_lost")]
+    OutputLost,
+    /// Task didn't finish before engine was shut down
+    #[postgres(name = "shutdown")]
+    Shutdown,
+    /// Worker panicked while processing the task
+    #[postgres(name = "panic")]
+    Panic,
+    /// Child process running the job exited (crash, abort, killed because of memory)
+    #[postgres(name = "crash")]
+    Crash,
+    /// Worker thread died while the task was queued for it or running in it
+    #[postgres(name = "worker_died")]
+    WorkerDied,
+    /// Engine process that claimed the task is no longer running
+    #[postgres(name = "engine_died")]
+    EngineDied,
+}
+
+#[allow(dead_code)]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct TaskSchema {
     pub id: i64,
@@ -21,8 +67,11 @@ pub struct TaskSchema {
     pub timestamp: i64,
     pub data: String,
     pub params: String,
+    pub attempt: i32,
+    pub retry_at: Option<i64>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ParentSchema {
     pub task_id: i64,
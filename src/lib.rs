@@ -0,0 +1,9 @@
+//! Journal, engine and workers of the image processing pipeline, shared by the GUI (`src/main.rs`)
+//! and the standalone worker (`src/bin/worker.rs`).
+
+pub mod database;
+pub mod engine;
+pub mod processing;
+pub mod temp;
+#[cfg(test)]
+mod tests_common;
@@ -1,9 +1,10 @@
-use database::common::Database;
+use database::common::{Database, ErrorType};
 use database::repositories::task::{InsertableTaskTree, Task, InsertableTask};
 use iced::{Application, Color, Command, Rectangle, Subscription};
-use log::{debug, warn};
+use log::{debug, error, warn};
 use processing::worker::WorkerErrorConfig;
 
+use std::collections::HashSet;
 use std::time::Duration;
 use std::{error::Error, vec};
 
@@ -22,21 +23,49 @@ use iced::{
 use nfd::Response;
 use std::path::PathBuf;
 
-mod database;
-mod engine;
-mod processing;
-mod temp;
-mod tests_common;
-
+use fault_tolerant_image_processing::{database, engine, processing};
 use processing::job::{self, BlurJob, BrightnessJob, CropJob, JobType, ResizeJob, OverlayJob};
 
-use crate::temp::from_temp;
+use fault_tolerant_image_processing::temp::from_temp;
 
 #[derive(Parser, Debug)]
 struct Args {
     /// Reset database
     #[clap(short, long, default_value_t = false)]
     reset: bool,
+    /// Number of threads for worker1 (Resize, Crop, Overlay)
+    #[clap(long, default_value_t = 1)]
+    worker1_threads: usize,
+    /// Number of threads for worker2 (Blur, Brightness)
+    #[clap(long, default_value_t = 1)]
+    worker2_threads: usize,
+    /// Cancel task with given id and exit
+    #[clap(long, value_name = "TASK_ID")]
+    cancel: Option<i64>,
+    /// Cancel also all tasks depending on cancelled task
+    #[clap(long, default_value_t = false, requires = "cancel")]
+    cascade: bool,
+    /// Retry dead task with given id and exit
+    #[clap(long, value_name = "TASK_ID")]
+    retry: Option<i64>,
+    /// Pause pipeline producing task with given id (the task and all tasks it depends on) and exit
+    #[clap(long, value_name = "TASK_ID")]
+    pause: Option<i64>,
+    /// Resume paused pipeline of task with given id and exit
+    #[clap(long, value_name = "TASK_ID")]
+    resume: Option<i64>,
+    /// Pause all threads of given worker type (e.g. worker1) and exit
+    #[clap(long, value_name = "NAME")]
+    pause_worker: Option<String>,
+    /// Resume paused worker type and exit
+    #[clap(long, value_name = "NAME")]
+    resume_worker: Option<String>,
+    /// Run jobs of every worker thread in a child process, so crash (abort, out of memory) of a job doesn't take down the app
+    #[clap(long, default_value_t = false)]
+    isolate_workers: bool,
+    /// Serve jobs of given worker as child process of isolated worker thread (see --isolate-workers)
+    #[clap(long, hide = true, value_name = "NAME")]
+    worker_process: Option<String>,
 }
 
 pub fn main() -> Result<(), Box<dyn Error>> {
@@ -50,15 +79,69 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     // init cli
     let args = Args::parse();
 
+    // child process of isolated worker thread, it doesn't touch the database
+    if let Some(name) = &args.worker_process {
+        processing::worker::default_registry(1, 1).serve_worker_process(name)?;
+        return Ok(());
+    }
+
     // reset db
     if args.reset {
         database::common::try_reset_database();
     }
 
+    let mut workers = processing::worker::default_registry(args.worker1_threads, args.worker2_threads);
+    if args.isolate_workers {
+        workers = workers.run_in_processes();
+    }
+    // main prints errors with Debug, message tells how to fix it
+    database::common::init_pool(workers.thread_count()).map_err(|e| e.to_string())?;
+
     let mut db = database::common::open_connection()?;
 
     database::migration::run_migrations(&mut db);
 
+    if let Some(task_id) = args.cancel {
+        let cancelled = db.cancel_task(task_id, args.cascade)?;
+        println!("Cancelled {} tasks", cancelled);
+        return Ok(());
+    }
+
+    if let Some(task_id) = args.retry {
+        db.retry_task(task_id)?;
+        println!("Task {} will be retried", task_id);
+        return Ok(());
+    }
+
+    if let Some(task_id) = args.pause {
+        db.pause_pipeline(task_id)?;
+        println!("Paused pipeline of task {}", task_id);
+        return Ok(());
+    }
+
+    if let Some(task_id) = args.resume {
+        db.resume_pipeline(task_id)?;
+        println!("Resumed pipeline of task {}", task_id);
+        return Ok(());
+    }
+
+    if let Some(name) = &args.pause_worker {
+        db.pause_worker(name)?;
+        println!("Paused worker {}", name);
+        return Ok(());
+    }
+
+    if let Some(name) = &args.resume_worker {
+        db.resume_worker(name)?;
+        println!("Resumed worker {}", name);
+        return Ok(());
+    }
+
+    // outputs of tasks interrupted by crash are never published, only their staging files are left
+    if let Err(e) = processing::data_loader::remove_orphaned_staging_files() {
+        warn!("Unable to remove orphaned staging files: {}", e);
+    }
+
     db.insert_new_task_tree(&InsertableTaskTree {
         data: None,
         status: database::schema::Status::Pending,
@@ -70,18 +153,22 @@ pub fn main() -> Result<(), Box<dyn Error>> {
                 status: database::schema::Status::Pending,
                 params: job::JobType::new_resize(512, 512),
                 parent_tasks: vec![InsertableTaskTree::input(&from_temp("in1.jpg"))],
+                priority: 0,
             },
             InsertableTaskTree::input(&from_temp("in2.jpg")),
         ],
+        priority: 0,
     })?;
 
-    let (_th, config) = run();
+    let worker_names = workers.names();
+    let (engine, config) = run(workers);
 
     //Styling::run(Settings::default())?;
-    MyApp::run(Settings {
-        flags: config,
-        ..Settings::default()
-    })?;
+    // GUI keeps connection used at startup, so it fails here if database is unreachable instead of blocking
+    MyApp::run(Settings::with_flags((config, worker_names, db)))?;
+
+    // window was closed, let workers finish what they are doing
+    engine.shutdown(Duration::from_secs(10));
 
     Ok(())
 }
@@ -104,18 +191,30 @@ impl<'a, Message: 'a> From<TaskElement> for iced::Element<'a, Message> {
         // display TaskElement - use diffrent backgorund for diffrent status
         // grey
         const PENDING: Color = Color::from_rgb(0.8, 0.8, 0.8);
+        // light blue - waiting in worker queue
+        const CLAIMED: Color = Color::from_rgb(0.6, 0.75, 0.9);
         // blue - 104 149 212
         const RUNNING: Color = Color::from_rgb(0.4, 0.6, 0.8);
         // red - 204 82 86
         const FAILED: Color = Color::from_rgb(0.8, 0.3, 0.3);
         // green 69 214 90
         const COMPLETED: Color = Color::from_rgb(0.3, 0.8, 0.3);
+        // dark red - out of attempts
+        const DEAD: Color = Color::from_rgb(0.5, 0.1, 0.1);
+        // dark grey
+        const CANCELLED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+        // orange - waits for dead ancestor
+        const BLOCKED: Color = Color::from_rgb(0.9, 0.6, 0.2);
 
         let background = match status {
             database::schema::Status::Pending => PENDING,
+            database::schema::Status::Claimed => CLAIMED,
             database::schema::Status::Running => RUNNING,
             database::schema::Status::Failed => FAILED,
             database::schema::Status::Completed => COMPLETED,
+            database::schema::Status::Dead => DEAD,
+            database::schema::Status::Cancelled => CANCELLED,
+            database::schema::Status::Blocked => BLOCKED,
         };
 
         struct RectangleProgram(Color, String);
@@ -169,7 +268,13 @@ impl<'a, Message: 'a> From<TaskElement> for iced::Element<'a, Message> {
 
 impl From<Task> for TaskElement {
     fn from(task: Task) -> Self {
-        let name = format!("{:?} {:?}", task.task_id, task.params);
+        let name = match task.status {
+            database::schema::Status::Dead => format!("{:?} {:?} DEAD after {} attempts: {}", task.task_id, task.params, task.attempt, task.error_message.unwrap_or_default()),
+            database::schema::Status::Failed => format!("{:?} {:?} (attempt {} failed: {:?})", task.task_id, task.params, task.attempt, task.failure_kind),
+            database::schema::Status::Blocked => format!("{:?} {:?} blocked by {}", task.task_id, task.params, task.blocked_by.unwrap_or_default()),
+            _ if task.attempt > 1 => format!("{:?} {:?} (attempt {})", task.task_id, task.params, task.attempt),
+            _ => format!("{:?} {:?}", task.task_id, task.params),
+        };
         TaskElement {
             id: task.task_id,
             name,
@@ -250,22 +355,47 @@ struct MyApp {
     selected_file: Option<PathBuf>,
     items: Vec<TaskElement>,
     panel_state: JobType,
+    priority: i32,
     choosed_input_state: Vec<Option<i64>>,
     current_action: AvalibleActions,
     db: Database,
     config: ConfigType,
     last_config: WorkerErrorConfig,
+    dead_count: usize,
+    cancel_choice: Option<i64>,
+    cancel_cascade: bool,
+    retry_choice: Option<i64>,
+    pause_choice: Option<i64>,
+    worker_names: Vec<String>,
+    paused_workers: HashSet<String>,
+    paused_pipelines: Vec<i64>,
 }
 
 impl MyApp {
     fn fetch_tasks(&mut self) {
+        // while database is unreachable, last fetched state is shown
+        if let Err(e) = self.try_fetch_tasks() {
+            warn!("Unable to fetch tasks: {}", e);
+        }
+    }
+
+    fn try_fetch_tasks(&mut self) -> Result<(), ErrorType> {
+        self.paused_pipelines = self.db.get_paused_pipelines()?;
+        self.paused_workers = self.db.get_paused_workers()?;
         self.items = self
             .db
-            .get_all_tasks()
-            .unwrap()
+            .get_all_tasks()?
             .into_iter()
             .map(|x| x.into())
+            .map(|mut x: TaskElement| {
+                if self.paused_pipelines.contains(&x.id) {
+                    x.name.push_str(" PAUSED");
+                }
+                x
+            })
             .collect();
+        self.dead_count = self.db.get_dead_tasks()?.len();
+        Ok(())
     }
 }
 
@@ -295,7 +425,7 @@ impl MyApp {
             list.into()
             
         }
-        match (action, self.panel_state) {
+        match (action, &self.panel_state) {
             (AvalibleActions::Crop, JobType::Crop(val)) => {
                 // crop has: x, y, width, height
                 let x = slider(0.0..=100.0, val.0 as f32, |x| {
@@ -388,6 +518,49 @@ impl MyApp {
         }
     }
 
+    fn cancel_controls(&self) -> Element<'_, Message> {
+        use database::schema::Status;
+
+        // only tasks that are not finished can be cancelled
+        let cancellable_tasks = self.items
+                                    .iter()
+                                    .filter(|x| !matches!(x.status, Status::Completed | Status::Dead | Status::Cancelled))
+                                    .map(|x| x.id)
+                                    .collect::<Vec<_>>();
+
+        let pick_list = pick_list::PickList::new(cancellable_tasks, self.cancel_choice, Message::CancelChoosed)
+            .width(Length::Fill);
+        let cascade = toggler(Some("Cascade".into()), self.cancel_cascade, Message::CancelCascadeChanged);
+        let cancel_button = Button::new(Text::new("Cancel task")).on_press(Message::CancelTask);
+
+        // dead tasks can be given new set of attempts
+        let dead_tasks = self.items
+                             .iter()
+                             .filter(|x| x.status == Status::Dead)
+                             .map(|x| x.id)
+                             .collect::<Vec<_>>();
+
+        let retry_pick_list = pick_list::PickList::new(dead_tasks, self.retry_choice, Message::RetryChoosed)
+            .width(Length::Fill);
+        let retry_button = Button::new(Text::new("Retry task")).on_press(Message::RetryTask);
+
+        // pipeline of any task can be paused, the task and everything it depends on waits until resumed
+        let all_tasks = self.items.iter().map(|x| x.id).collect::<Vec<_>>();
+
+        let pause_pick_list = pick_list::PickList::new(all_tasks, self.pause_choice, Message::PauseChoosed)
+            .width(Length::Fill);
+        let pause_button = Button::new(Text::new("Pause pipeline")).on_press(Message::PausePipeline);
+        let resume_button = Button::new(Text::new("Resume pipeline")).on_press(Message::ResumePipeline);
+
+        column![
+            row![pick_list, cascade, cancel_button].spacing(5),
+            row![retry_pick_list, retry_button].spacing(5),
+            row![pause_pick_list, pause_button, resume_button].spacing(5),
+        ]
+        .spacing(5)
+        .into()
+    }
+
     fn config_controls(&self) -> Element<'_, Message> {
         const THROTTLE_RANGE: f32 = 2.0;
         let config = self.last_config;
@@ -404,15 +577,25 @@ impl MyApp {
         });
         let add_button = Button::new(Text::new("Add Item")).on_press(Message::AddItem);
 
+        let paused_workers = self.worker_names.iter().fold(row![].spacing(5), |row, name| {
+            let worker = name.clone();
+            row.push(toggler(Some(format!("Pause {}", name)), self.paused_workers.contains(name), move |x| {
+                Message::WorkerPausedChanged(worker.clone(), x)
+            }))
+        });
+
         column![row![
             Text::new("Throttle"),
             throttle,
             Text::new("Error Chance"),
             error_chance,
             paused,
-            add_button
+            add_button,
+            Text::new(format!("Dead tasks: {}", self.dead_count))
         ]
-        .spacing(5),]
+        .spacing(5),
+        paused_workers,]
+        .spacing(5)
         .into()
     }
 }
@@ -432,6 +615,16 @@ enum Message {
     PausedChanged(bool),
     PeriodicEvent,
     InputChoosed(i64, i64),
+    CancelChoosed(i64),
+    CancelCascadeChanged(bool),
+    CancelTask,
+    RetryChoosed(i64),
+    RetryTask,
+    PauseChoosed(i64),
+    PausePipeline,
+    ResumePipeline,
+    WorkerPausedChanged(String, bool),
+    PriorityChanged(f32),
 }
 
 impl MyApp {
@@ -513,11 +706,11 @@ impl MyApp {
 
 impl Application for MyApp {
     type Message = Message;
-    type Flags = ConfigType;
+    type Flags = (ConfigType, Vec<String>, Database);
     type Executor = iced::executor::Default;
     type Theme = Theme;
 
-    fn new(settings: Self::Flags) -> (Self, Command<Self::Message>) {
+    fn new((settings, worker_names, db): Self::Flags) -> (Self, Command<Self::Message>) {
         let last_config = *settings.read().unwrap();
         (
             MyApp {
@@ -525,10 +718,19 @@ impl Application for MyApp {
                 items: vec![],
                 choosed_input_state: vec![],
                 panel_state: JobType::Crop(CropJob(0, 0, 0, 0)),
+                priority: 0,
                 current_action: AvalibleActions::Crop,
-                db: database::common::try_open_connection(),
+                db,
                 config: settings,
                 last_config,
+                dead_count: 0,
+                cancel_choice: None,
+                cancel_cascade: true,
+                retry_choice: None,
+                pause_choice: None,
+                worker_names,
+                paused_workers: HashSet::new(),
+                paused_pipelines: vec![],
             },
             Command::none(),
         )
@@ -580,7 +782,9 @@ impl Application for MyApp {
                     AvalibleActions::Input => {
                         if let Some(path) = &self.selected_file {
                             let path = path.to_str().unwrap();
-                            self.db.insert_new_task(&InsertableTask::input(path)).unwrap();
+                            if let Err(e) = self.db.insert_new_task(&InsertableTask::input(path)) {
+                                error!("Unable to insert task: {}", e);
+                            }
                         } else {
                             warn!("No input file selected");
                         }
@@ -598,10 +802,13 @@ impl Application for MyApp {
                                     parent_ids: inputs,
                                     status: database::schema::Status::Pending,
                                     data: None,
-                                    params: *panel_state,
+                                    params: panel_state.clone(),
+                                    priority: self.priority,
                                 };
 
-                                self.db.insert_new_task(&task).unwrap();
+                                if let Err(e) = self.db.insert_new_task(&task) {
+                                    error!("Unable to insert task: {}", e);
+                                }
                             } else {
                                 warn!("No input file selected");
                             }
@@ -636,6 +843,58 @@ impl Application for MyApp {
                 }
             }
             Message::PeriodicEvent => self.fetch_tasks(),
+            Message::CancelChoosed(id) => self.cancel_choice = Some(id),
+            Message::CancelCascadeChanged(value) => self.cancel_cascade = value,
+            Message::CancelTask => {
+                if let Some(task_id) = self.cancel_choice.take() {
+                    if let Err(e) = self.db.cancel_task(task_id, self.cancel_cascade) {
+                        error!("Unable to cancel task: {}", e);
+                    }
+                } else {
+                    warn!("No task to cancel selected");
+                }
+            }
+            Message::PriorityChanged(value) => self.priority = value as i32,
+            Message::RetryChoosed(id) => self.retry_choice = Some(id),
+            Message::RetryTask => {
+                if let Some(task_id) = self.retry_choice.take() {
+                    if let Err(e) = self.db.retry_task(task_id) {
+                        error!("Unable to retry task: {}", e);
+                    }
+                } else {
+                    warn!("No task to retry selected");
+                }
+            }
+            Message::PauseChoosed(id) => self.pause_choice = Some(id),
+            Message::PausePipeline => {
+                if let Some(task_id) = self.pause_choice {
+                    if let Err(e) = self.db.pause_pipeline(task_id) {
+                        error!("Unable to pause pipeline: {}", e);
+                    }
+                } else {
+                    warn!("No task to pause selected");
+                }
+            }
+            Message::ResumePipeline => {
+                if let Some(task_id) = self.pause_choice {
+                    if let Err(e) = self.db.resume_pipeline(task_id) {
+                        error!("Unable to resume pipeline: {}", e);
+                    }
+                } else {
+                    warn!("No task to resume selected");
+                }
+            }
+            Message::WorkerPausedChanged(name, value) => {
+                if value {
+                    if let Err(e) = self.db.pause_worker(&name) {
+                        error!("Unable to pause worker: {}", e);
+                    }
+                } else {
+                    if let Err(e) = self.db.resume_worker(&name) {
+                        error!("Unable to resume worker: {}", e);
+                    }
+                }
+            }
             Message::InputChoosed(id, index) => {
                 if let Some(state) = self.choosed_input_state.get_mut(index as usize) {
                     *state = Some(id);
@@ -657,7 +916,7 @@ impl Application for MyApp {
         time::every(Duration::from_millis(250)).map(|_| Message::PeriodicEvent)
     }
 
-    fn view(self: &MyApp) -> Element<Message> {
+    fn view(self: &MyApp) -> Element<'_, Message> {
         let scrollable_content = self
             .items
             .iter()
@@ -684,6 +943,12 @@ impl Application for MyApp {
 
         let action_panel = self.action_to_panel(self.current_action);
 
+        let priority = row![
+            Text::new(format!("Priority {}", self.priority)),
+            slider(0.0..=10.0, self.priority as f32, Message::PriorityChanged),
+        ]
+        .spacing(5);
+
         let show_job_button = Button::new(Text::new("Add task"))
             .on_press(Message::ConfirmJob)
             .width(Length::Fill);
@@ -693,7 +958,7 @@ impl Application for MyApp {
 
         let layout = row![
             column![column].width(300),
-            column![pick_list, action_panel, show_job_button]
+            column![pick_list, action_panel, priority, show_job_button, self.cancel_controls()]
                 .spacing(10)
                 .width(Length::Fill),
         ]
@@ -1,7 +1,19 @@
 use std::fmt::Formatter;
-use log::{debug};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use log::{debug, error, info, warn};
 
-use image::{RgbImage, ImageError};
+use image::{RgbImage, ImageError, ImageFormat};
+
+use crate::temp::TEMP;
+
+/// Output is written to file with this suffix and renamed to its final name once it is complete.
+const STAGING_SUFFIX: &str = ".staging";
+/// Staging file not touched for that long is left by a crashed writer (any write takes far less),
+/// younger ones may belong to other process using the same temp directory.
+const STAGING_MAX_AGE: Duration = Duration::from_secs(10 * 60);
 
 
 
@@ -19,6 +31,11 @@ impl From<ImageError> for DataLoaderError {
         DataLoaderError
     }
 }
+impl From<io::Error> for DataLoaderError {
+    fn from(_: io::Error) -> Self {
+        DataLoaderError
+    }
+}
 impl std::error::Error for DataLoaderError {}
 
 pub fn load_image(path: &str) -> Result<RgbImage, DataLoaderError> {
@@ -26,8 +43,110 @@ pub fn load_image(path: &str) -> Result<RgbImage, DataLoaderError> {
     Ok(image::open(path)?.to_rgb8())
 }
 
+/// Saves image to `path` atomically - it is written to staging file, synced to disk and decoded back to check it,
+/// then renamed to `path`. Crash in the middle leaves only staging file, never truncated output.
 pub fn save_image_with_path(path: &str, image: &RgbImage) -> Result<(), DataLoaderError> {
     debug!("saving {}", path);
-    image.save(path)?;
+    let format = ImageFormat::from_path(path)?;
+    let staging = format!("{}{}", path, STAGING_SUFFIX);
+
+    let published = write_staging(&staging, image, format).and_then(|()| {
+        fs::rename(&staging, path)?;
+        sync_parent(path);
+        Ok(())
+    });
+
+    if published.is_err() {
+        fs::remove_file(&staging).ok();
+    }
+    published
+}
+
+fn write_staging(staging: &str, image: &RgbImage, format: ImageFormat) -> Result<(), DataLoaderError> {
+    let mut writer = BufWriter::new(File::create(staging)?);
+    image.write_to(&mut writer, format)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    let written = image::load(BufReader::new(File::open(staging)?), format)?.to_rgb8();
+    if written != *image {
+        error!("Output {} doesn't match processed image", staging);
+        return Err(DataLoaderError);
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Syncs directory of `path`, so rename is on disk too. Not every platform can open directory, then it is skipped.
+fn sync_parent(path: &str) {
+    if let Some(dir) = Path::new(path).parent() {
+        if let Ok(dir) = File::open(dir) {
+            dir.sync_all().ok();
+        }
+    }
+}
+
+/// Removes staging files left in temp directory by writers that crashed, returns how many were removed.
+pub fn remove_orphaned_staging_files() -> io::Result<usize> {
+    let mut removed = 0;
+
+    for entry in fs::read_dir(&*TEMP)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().ends_with(STAGING_SUFFIX) {
+            continue;
+        }
+
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age < STAGING_MAX_AGE {
+            continue;
+        }
+
+        match fs::remove_file(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Unable to remove staging file {}: {}", entry.path().display(), e),
+        }
+    }
+
+    if removed > 0 {
+        info!("Removed {} orphaned staging files", removed);
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serial_test::serial;
+
+    use crate::temp::from_temp;
+
+    use super::{load_image, remove_orphaned_staging_files, save_image_with_path};
+
+    #[test]
+    #[serial]
+    fn output_is_published_only_when_complete() {
+        let path = from_temp("published-test.bmp");
+        let image = image::RgbImage::from_fn(4, 4, |x, y| image::Rgb([x as u8, y as u8, 7]));
+
+        save_image_with_path(&path, &image).unwrap();
+
+        assert_eq!(load_image(&path).unwrap(), image);
+        assert!(!std::path::Path::new(&format!("{}.staging", path)).exists());
+        std::fs::remove_file(&path).unwrap();
+
+        // staging file of crashed writer is removed, the one being written by other process is kept
+        let orphaned = from_temp("orphaned-test.bmp.staging");
+        let written = from_temp("written-test.bmp.staging");
+        let hour_ago = std::time::SystemTime::now() - Duration::from_secs(60 * 60);
+        std::fs::File::create(&orphaned).unwrap().set_modified(hour_ago).unwrap();
+        std::fs::File::create(&written).unwrap();
+
+        remove_orphaned_staging_files().unwrap();
+
+        assert!(!std::path::Path::new(&orphaned).exists());
+        assert!(std::path::Path::new(&written).exists());
+        std::fs::remove_file(&written).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ResizeJob(pub u32, pub u32);
@@ -11,7 +11,15 @@ pub struct BrightnessJob(pub f32);
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct OverlayJob(pub u32, pub u32);
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Job of operation that is not built in, processed by worker registered for its kind.
+/// Stored as `{"<kind>": <params>}`, the same way as built-in jobs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomJob {
+    pub kind: String,
+    pub params: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
 pub enum JobType {
     Resize(ResizeJob),
     Crop(CropJob),
@@ -19,6 +27,62 @@ pub enum JobType {
     Brightness(BrightnessJob),
     Overlay(OverlayJob),
     Input,
+    Custom(CustomJob),
+}
+
+/// Names of kinds of built-in jobs. Kind of a job is its tag in serialized params (and `kind` column of tasks),
+/// workers are registered for kinds they can process.
+pub mod kinds {
+    pub const RESIZE: &str = "Resize";
+    pub const CROP: &str = "Crop";
+    pub const BLUR: &str = "Blur";
+    pub const BRIGHTNESS: &str = "Brightness";
+    pub const OVERLAY: &str = "Overlay";
+    pub const INPUT: &str = "Input";
+
+    pub const BUILT_IN: [&str; 6] = [RESIZE, CROP, BLUR, BRIGHTNESS, OVERLAY, INPUT];
+}
+
+/// Serialized form of built-in jobs.
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "JobType")]
+enum JobTypeDef {
+    Resize(ResizeJob),
+    Crop(CropJob),
+    Blur(BlurJob),
+    Brightness(BrightnessJob),
+    Overlay(OverlayJob),
+    Input,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    Custom(CustomJob),
+}
+
+impl Serialize for JobType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            JobType::Custom(job) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(&job.kind, &job.params)?;
+                map.end()
+            }
+            _ => JobTypeDef::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for JobType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        match value {
+            serde_json::Value::Object(map) if map.len() == 1 && !kinds::BUILT_IN.contains(&map.keys().next().unwrap().as_str()) => {
+                let (kind, params) = map.into_iter().next().unwrap();
+                Ok(JobType::Custom(CustomJob { kind, params }))
+            }
+            value => JobTypeDef::deserialize(value).map_err(D::Error::custom),
+        }
+    }
 }
 
 impl JobType {
@@ -48,6 +112,19 @@ impl JobType {
         JobType::Input
     }
 
+    /// Name of kind of the job, tasks are dispatched to worker registered for it.
+    pub fn kind(&self) -> &str {
+        match self {
+            JobType::Resize(_) => kinds::RESIZE,
+            JobType::Crop(_) => kinds::CROP,
+            JobType::Blur(_) => kinds::BLUR,
+            JobType::Brightness(_) => kinds::BRIGHTNESS,
+            JobType::Overlay(_) => kinds::OVERLAY,
+            JobType::Input => kinds::INPUT,
+            JobType::Custom(job) => &job.kind,
+        }
+    }
+
     pub fn input_count(&self) -> usize {
         match self {
             JobType::Resize(_) => 1,
@@ -56,6 +133,8 @@ impl JobType {
             JobType::Brightness(_) => 1,
             JobType::Overlay(_) => 2,
             JobType::Input => 0,
+            // custom job takes all parents the task was added with
+            JobType::Custom(_) => 0,
         }
     }
 }
@@ -108,7 +187,7 @@ where
                 .collect::<Vec<_>>()
         }
 
-        let input: _ = load_images_from_task_parents(&task.parent_tasks.unwrap());
+        let input = load_images_from_task_parents(&task.parent_tasks.unwrap());
 
         let task = match task.params.try_into() {
             Ok(task) => task,
@@ -125,3 +204,54 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serial_test::serial;
+
+    use crate::database::repositories::task::InsertableTask;
+    use crate::database::schema::Status;
+    use crate::tests_common::*;
+
+    use super::{kinds, CustomJob, JobType};
+
+    fn sharpen(amount: f32) -> JobType {
+        JobType::Custom(CustomJob { kind: "Sharpen".to_string(), params: json!({ "amount": amount }) })
+    }
+
+    #[test]
+    fn custom_job_is_stored_like_built_in_ones() {
+        assert_eq!(serde_json::to_string(&JobType::new_blur(1.0)).unwrap(), r#"{"Blur":1.0}"#);
+        assert_eq!(serde_json::to_string(&sharpen(2.0)).unwrap(), r#"{"Sharpen":{"amount":2.0}}"#);
+
+        let job: JobType = serde_json::from_str(r#"{"Sharpen":{"amount":2.0}}"#).unwrap();
+        assert_eq!(job.kind(), "Sharpen");
+        assert!(matches!(job, JobType::Custom(CustomJob { params, .. }) if params == json!({ "amount": 2.0 })));
+
+        let job: JobType = serde_json::from_str(r#"{"Blur":1.0}"#).unwrap();
+        assert_eq!(job.kind(), kinds::BLUR);
+        // built-in job with broken params is not taken for custom one
+        assert!(serde_json::from_str::<JobType>(r#"{"Blur":"x"}"#).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn custom_job_is_claimed_by_its_kind() {
+        let mut db = init_database();
+
+        db.insert_new_task(&InsertableTask {
+            parent_ids: vec![],
+            status: Status::Pending,
+            data: None,
+            params: sharpen(2.0),
+            priority: 0,
+        }).unwrap();
+
+        assert!(db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap().is_empty());
+
+        let claimed = db.claim_runnable_tasks_of_kinds(&["Sharpen"], None).unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].params.kind(), "Sharpen");
+    }
+}
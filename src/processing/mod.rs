@@ -1,3 +1,3 @@
 pub mod worker;
 pub mod job;
-mod data_loader;
\ No newline at end of file
+pub mod data_loader;
\ No newline at end of file
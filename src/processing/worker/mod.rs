@@ -1,14 +1,22 @@
+pub mod outbox;
+pub mod registry;
+pub mod subprocess;
 pub mod worker1;
 pub mod worker2;
 
 use image::RgbImage;
 use log::{error, info, warn};
 use std::{
+    any::Any,
+    collections::HashMap,
     marker::PhantomData,
+    panic::AssertUnwindSafe,
     sync::{
-        mpsc::{self}
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self},
+        Arc, Mutex,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use uuid::Uuid;
@@ -16,18 +24,34 @@ use uuid::Uuid;
 use crate::{
     database::{
         common::{Database, ErrorType},
-        repositories::task::Task,
+        repositories::task::{Failure, Task},
+        schema::FailureKind,
     },
     processing::data_loader::save_image_with_path,
     temp::from_temp, engine::ConfigType,
 };
 
-use super::job::{Job, JobType};
+use self::{outbox::{Outbox, Report}, registry::WorkerRegistry, subprocess::ChildWorker, worker1::Worker1, worker2::Worker2};
+
+use super::job::{kinds, Job, JobType};
+
+/// How often worker renews lease of task it is processing.
+pub const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(1);
+/// How long thread claiming its own tasks waits when there is nothing to do.
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often paused thread checks if it was resumed.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often idle thread tries to write results waiting in its outbox.
+const OUTBOX_REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+/// How long worker keeps trying to start task while database is unreachable before giving up on it.
+const DATABASE_RETRY_TIMEOUT: Duration = Duration::from_secs(30);
+const DATABASE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone, Copy)]
 pub struct WorkerErrorConfig {
     pub throttle: Duration,
     pub random_error_chance: f32,
+    #[allow(dead_code)]
     pub random_not_save_chance: f32,
     pub paused: bool,
 }
@@ -38,43 +62,139 @@ impl Default for WorkerErrorConfig{
     }
 }
 
+/// Registry with built-in workers: worker1 for geometry operations, worker2 for filters.
+pub fn default_registry(worker1_threads: usize, worker2_threads: usize) -> WorkerRegistry {
+    WorkerRegistry::new()
+        .register("worker1", &[kinds::RESIZE, kinds::CROP, kinds::OVERLAY], worker1_threads, Worker1::new)
+        .register("worker2", &[kinds::BLUR, kinds::BRIGHTNESS], worker2_threads, Worker2::new)
+}
+
+/// Error of processing a job. Permanent errors (like invalid parameters) fail the task without retries.
+#[derive(Debug, Clone)]
+pub struct ProcessError {
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl ProcessError {
+    /// Error that may not happen on next attempt.
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self { message: message.into(), retryable: true }
+    }
+
+    /// Error that would happen on every attempt.
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self { message: message.into(), retryable: false }
+    }
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.retryable { "transient" } else { "permanent" };
+        write!(f, "{} ({})", self.message, kind)
+    }
+}
+
+impl From<ProcessError> for Failure {
+    fn from(e: ProcessError) -> Self {
+        Failure { kind: FailureKind::ProcessError, message: e.message, retryable: e.retryable }
+    }
+}
+
 pub trait ImageWorker {
     type WorkerJob: TryFrom<JobType> + Send;
 
-    fn process(&mut self, job: Job<Self::WorkerJob>) -> Result<RgbImage, ()>;
+    fn process(&mut self, job: Job<Self::WorkerJob>) -> Result<RgbImage, ProcessError>;
+}
+
+/// State shared between worker thread and its handle.
+#[derive(Default)]
+struct WorkerThreadShared {
+    /// Set when engine shuts down, queued tasks are released instead of processed.
+    stopping: AtomicBool,
+    /// Task (id, claim token) that is being processed right now.
+    current_task: Mutex<Option<(i64, i64)>>,
+    /// Claim tokens of tasks queued for the thread or being processed by it, by task id.
+    /// Returned to pending if the thread dies.
+    in_flight: Mutex<HashMap<i64, i64>>,
 }
 
 pub struct WorkerThread<Worker: ImageWorker + Send> {
+    /// Name of the thread, recorded in the journal as worker of tasks it runs.
+    name: String,
+    /// Name of registered worker type of the thread, used to pause it.
+    worker: String,
     thread: Option<(
         std::thread::JoinHandle<()>,
-        mpsc::Sender<Task>,
+        Option<mpsc::Sender<Task>>,
     )>,
+    shared: Arc<WorkerThreadShared>,
+    /// Job kinds claimed by the thread itself. If None, thread receives tasks from scheduler.
+    claims: Option<Vec<String>>,
+    /// Name of registered worker run in child process for every job. If None, jobs are run in the thread.
+    process: Option<String>,
     phantom: PhantomData<Worker>,
 }
 
 impl<Worker: ImageWorker + Send + 'static> WorkerThread<Worker> {
-    pub fn new() -> Self {
+    pub fn new(name: String, worker: &str) -> Self {
         Self {
+            name,
+            worker: worker.to_string(),
             thread: None,
-
+            shared: Arc::default(),
+            claims: None,
+            process: None,
             phantom: PhantomData,
         }
     }
 
-    pub fn start(&mut self, worker: Worker, journal: Database, config: ConfigType) {
-        let (tx, rx) = mpsc::channel();  
-        let thread = std::thread::spawn(move || {
-            Self::thread_body(worker, journal, rx, config);
-        });        
+    pub fn start(&mut self, worker: Worker, journal: Database, outbox: Outbox, config: ConfigType) {
+        let shared = Arc::new(WorkerThreadShared::default());
+        let thread_shared = shared.clone();
+        let executor = match &self.process {
+            Some(name) => Executor::Process(ChildWorker::new(name)),
+            None => Executor::Thread(worker),
+        };
+        let pause = Pause { worker: self.worker.clone(), config };
 
-        self.thread = Some((thread, tx));
+        self.thread = Some(match self.claims.clone() {
+            Some(kinds) => {
+                let thread = self.spawn(move || {
+                    Self::claiming_thread_body(executor, journal, outbox, kinds, pause, thread_shared);
+                });
+                (thread, None)
+            }
+            None => {
+                let (tx, rx) = mpsc::channel();  
+                let thread = self.spawn(move || {
+                    Self::thread_body(executor, journal, outbox, rx, pause, thread_shared);
+                });
+                (thread, Some(tx))
+            }
+        });
+        self.shared = shared;
+    }
+
+    fn spawn(&self, body: impl FnOnce() + Send + 'static) -> std::thread::JoinHandle<()> {
+        std::thread::Builder::new()
+            .name(self.name.clone())
+            .spawn(body)
+            .expect("unable to spawn worker thread")
     }
 
     pub fn send_task(&mut self, task: Task) -> Result<(), ErrorType> {
-        if let Some((_, tx)) = &self.thread {
+        if let Some((_, Some(tx))) = &self.thread {
+            let task_id = task.task_id;
+            let claim_token = task.claim_token.expect("task sent to worker has to be claimed");
+            self.shared.in_flight.lock().unwrap().insert(task_id, claim_token);
+
             match tx.send(task) {
                 Ok(_) => Ok(()),
-                Err(_) => Err(ErrorType::WorkerThreadFailed),
+                Err(_) => {
+                    self.shared.in_flight.lock().unwrap().remove(&task_id);
+                    Err(ErrorType::WorkerThreadFailed)
+                }
             }
         } else {
             Err(ErrorType::WorkerThreadFailed)
@@ -89,81 +209,624 @@ impl<Worker: ImageWorker + Send + 'static> WorkerThread<Worker> {
         }
     }
 
+    /// Makes thread claim tasks of given `kinds` from database by itself instead of receiving them from scheduler.
+    /// Takes effect when thread is (re)started.
+    pub fn claim_own_tasks(&mut self, kinds: &[String]) {
+        self.claims = Some(kinds.to_vec());
+    }
+
+    /// Makes thread run every job in child process with worker `name`, so crash of the job doesn't affect the app.
+    /// Takes effect when thread is (re)started.
+    pub fn run_in_process(&mut self, name: &str) {
+        self.process = Some(name.to_string());
+    }
+
+    /// Asks thread to finish its current task and release tasks left in its queue.
+    pub fn stop(&mut self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+
+        // thread exits once queue is drained
+        if let Some((_, tx)) = &mut self.thread {
+            tx.take();
+        }
+    }
+
+    /// Waits for stopped thread until `deadline`.
+    /// Returns task (id, claim token) the thread was still processing if it didn't finish in time.
+    pub fn join(&mut self, deadline: Instant) -> Option<(i64, i64)> {
+        let (thread, _) = self.thread.take()?;
+
+        while !thread.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        if thread.is_finished() {
+            if thread.join().is_err() {
+                warn!("Worker thread panicked during shutdown");
+            }
+            None
+        } else {
+            // thread is left detached, its late result will be rejected by claim token
+            *self.shared.current_task.lock().unwrap()
+        }
+    }
+
+    /// Starts thread if it is not running. If connection for it can't be opened, thread is started on next call.
     pub fn restore_thread<F>(&mut self, f: F)
     where
-        F: FnOnce() -> (Worker, Database, ConfigType),
+        F: FnOnce() -> Result<(Worker, Database, ConfigType), ErrorType>,
     {
         // check if thread is alive
+        if !self.thread_died() {
+            return;
+        }
+
+        let (worker, mut journal, config) = match f() {
+            Ok(started) => started,
+            Err(e) => {
+                warn!("Unable to start worker thread {}: {}", self.name, e);
+                return;
+            }
+        };
+        // outbox of dead thread was closed when it exited
+        let outbox = match Outbox::open(&self.name) {
+            Ok(outbox) => outbox,
+            Err(e) => {
+                warn!("Unable to open outbox of worker thread {}: {}", self.name, e);
+                return;
+            }
+        };
+
         if self.thread.is_none() {
-            let (worker, journal, config) = f();
             info!("Starting worker thread");
-            self.start(worker, journal, config);
-        } else if self.thread_died() && self.thread_died() {
-            let (worker, journal, config) = f();
+        } else {
             warn!("Thread died. Restarting...");
-            self.start(worker, journal, config);
+            self.requeue_lost_tasks(&mut journal);
+        }
+        self.start(worker, journal, outbox, config);
+    }
+
+    /// Returns tasks the dead thread had queued or was processing back to pending.
+    fn requeue_lost_tasks(&self, journal: &mut Database) {
+        let lost = std::mem::take(&mut *self.shared.in_flight.lock().unwrap());
+
+        for (task_id, claim_token) in lost {
+            match journal.requeue_lost_task(task_id, claim_token, &self.name) {
+                Ok(()) => info!("Requeued task {} lost in dead thread {}", task_id, self.name),
+                // task was finished, cancelled or re-dispatched before the thread died
+                Err(ErrorType::TaskCancelled(_) | ErrorType::StaleClaim(_)) => {}
+                Err(e) => warn!("Unable to requeue task {}: {}", task_id, e),
+            }
         }
     }
 
     fn thread_body(
-        mut worker: Worker,
+        mut executor: Executor<Worker>,
         mut journal: Database,
+        mut outbox: Outbox,
         channel: mpsc::Receiver<Task>,
-        config: ConfigType,
+        pause: Pause,
+        shared: Arc<WorkerThreadShared>,
     ) {
         loop {
-            let task = channel.recv().unwrap();
+            let task = match channel.recv_timeout(OUTBOX_REPLAY_INTERVAL) {
+                Ok(task) => task,
+                // results waiting in outbox are written once database is back, even if no more tasks come
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    outbox.replay(&mut journal).ok();
+                    continue;
+                }
+                // channel is closed when thread is stopped and all queued tasks were received
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
             let task_id = task.task_id;
-            let filename = from_temp(&format!("{}.bmp", Uuid::new_v4()));
+            let claim_token = task.claim_token.expect("task sent to worker has to be claimed");
 
-            if config.read().unwrap().paused {
-                std::thread::sleep(Duration::from_millis(100));
-                continue;
+            // task is kept at the head of the queue while paused, so queued tasks are resumed in order
+            while !shared.stopping.load(Ordering::SeqCst) && pause.is_paused(&mut journal) {
+                std::thread::sleep(PAUSE_POLL_INTERVAL);
             }
 
-            let result = match Job::<Worker::WorkerJob>::from_task(task) {
-                Ok(job) => {
-                    info!("Received task: {}", task_id);
+            if shared.stopping.load(Ordering::SeqCst) {
+                match journal.release_claim(task_id, claim_token) {
+                    Ok(()) => info!("Released task {} on shutdown", task_id),
+                    Err(e) => warn!("Unable to release task {}: {}", task_id, e),
+                }
+            } else if pause.is_pipeline_paused(&mut journal, task_id) {
+                // tasks of other pipelines are not held up, this one is not claimed again until its pipeline is resumed
+                match journal.release_claim(task_id, claim_token) {
+                    Ok(()) => info!("Released task {} of paused pipeline", task_id),
+                    Err(e) => warn!("Unable to release task {}: {}", task_id, e),
+                }
+            } else {
+                Self::process_task(&mut executor, &mut journal, &mut outbox, &pause.config, &shared, task);
+            }
+
+            shared.in_flight.lock().unwrap().remove(&task_id);
+        }
+    }
+
+    /// Body of thread that claims tasks of given `kinds` from database by itself, one at a time.
+    fn claiming_thread_body(
+        mut executor: Executor<Worker>,
+        mut journal: Database,
+        mut outbox: Outbox,
+        kinds: Vec<String>,
+        pause: Pause,
+        shared: Arc<WorkerThreadShared>,
+    ) {
+        while !shared.stopping.load(Ordering::SeqCst) {
+            outbox.replay(&mut journal).ok();
+
+            if pause.is_paused(&mut journal) {
+                std::thread::sleep(PAUSE_POLL_INTERVAL);
+                continue;
+            }
 
-                    // fail randomly with chance
-                    if rand::random::<f32>() < config.read().unwrap().random_error_chance {
-                        error!("Random error");
-                        panic!("random error");
+            match journal.claim_runnable_tasks_of_kinds(&kinds, Some(1)) {
+                Ok(mut tasks) => match tasks.pop() {
+                    Some(task) => {
+                        let task_id = task.task_id;
+                        shared.in_flight.lock().unwrap().insert(task_id, task.claim_token.expect("claimed task has claim token"));
+                        Self::process_task(&mut executor, &mut journal, &mut outbox, &pause.config, &shared, task);
+                        shared.in_flight.lock().unwrap().remove(&task_id);
                     }
+                    None => std::thread::sleep(CLAIM_POLL_INTERVAL),
+                },
+                Err(e) => {
+                    // thread is restarted with new connection
+                    error!("Unable to claim tasks: {}", e);
+                    return;
+                }
+            }
+        }
+    }
 
-                    match worker.process(job) {
-                        Ok(image) => {
-                            info!("Job processed successfully");
+    /// Runs claimed task and reports its result to the journal.
+    fn process_task(
+        executor: &mut Executor<Worker>,
+        journal: &mut Database,
+        outbox: &mut Outbox,
+        config: &ConfigType,
+        shared: &WorkerThreadShared,
+        task: Task,
+    ) {
+        let task_id = task.task_id;
+        let claim_token = task.claim_token.expect("task sent to worker has to be claimed");
+        let filename = from_temp(&format!("{}.bmp", Uuid::new_v4()));
 
-                            // save_image(&image).unwrap();
-                            save_image_with_path(&filename, &image).unwrap();
+        if let Err(e) = retry_on_connection_error(|| journal.mark_task_as_running(task_id, claim_token, &worker_id())) {
+            warn!("Task {} is no longer claimed, skipping it ({})", task_id, e);
+            return;
+        }
+        // database is reachable again, so results waiting in outbox are written before leases of their tasks expire
+        outbox.replay(journal).ok();
+        *shared.current_task.lock().unwrap() = Some((task_id, claim_token));
 
-                            Ok(())
-                        }
-                        Err(_) => {
-                            warn!("Error processing job");
+        // failed with reason and ids of parents which outputs were missing
+        let random_error_chance = config.read().unwrap().random_error_chance;
+        let result = with_lease(journal, task_id, claim_token, || executor.execute(task, &filename, random_error_chance));
 
-                            Err(())
-                        }
-                    }
+        // result is kept in outbox if database is unreachable, so finished work is not lost
+        outbox.report(journal, Report { task_id, claim_token, output: filename, result });
+        *shared.current_task.lock().unwrap() = None;
+
+        // sleep
+        std::thread::sleep(config.read().unwrap().throttle);
+    }
+}
+
+/// Pause switches checked by worker thread - global one in config, one of its worker type and of pipelines in database.
+/// Global and worker pauses hold the queue, task of paused pipeline is given back.
+struct Pause {
+    worker: String,
+    config: ConfigType,
+}
+
+impl Pause {
+    fn is_paused(&self, journal: &mut Database) -> bool {
+        if self.config.read().unwrap().paused {
+            return true;
+        }
+
+        // thread doesn't stop on database errors here, they are handled when task is reported
+        journal.is_worker_paused(&self.worker).unwrap_or_else(|e| {
+            warn!("Unable to check if {} is paused: {}", self.worker, e);
+            false
+        })
+    }
+
+    fn is_pipeline_paused(&self, journal: &mut Database, task_id: i64) -> bool {
+        journal.is_task_paused(task_id).unwrap_or_else(|e| {
+            warn!("Unable to check if pipeline of task {} is paused: {}", task_id, e);
+            false
+        })
+    }
+}
+
+/// Result of a job - error with failure reason and parents which outputs were missing,
+/// as ids with claims that completed them.
+pub type JobResult = Result<(), (Failure, Vec<(i64, Option<i64>)>)>;
+
+/// Runs jobs of worker thread, either in the thread itself or in child process.
+enum Executor<Worker> {
+    Thread(Worker),
+    Process(ChildWorker),
+}
+
+impl<Worker: ImageWorker> Executor<Worker> {
+    fn execute(&mut self, task: Task, output: &str, random_error_chance: f32) -> JobResult {
+        match self {
+            Executor::Thread(worker) => execute_job(worker, task, output, random_error_chance),
+            Executor::Process(child) => child.execute(task, output, random_error_chance),
+        }
+    }
+}
+
+/// Loads inputs of the task, processes it and saves output to `output`.
+/// Panic fails only the task, so thread (or child process) keeps serving next ones.
+pub fn execute_job<Worker: ImageWorker>(worker: &mut Worker, task: Task, output: &str, random_error_chance: f32) -> JobResult {
+    let task_id = task.task_id;
+    // output of parent is failed only if it is still the one that couldn't be loaded
+    let parent_claims = task.parent_tasks.iter().flatten()
+        .map(|parent| (parent.task_id, parent.claim_token))
+        .collect::<HashMap<_, _>>();
+
+    let run = || match Job::<Worker::WorkerJob>::from_task(task) {
+        Ok(job) => {
+            info!("Received task: {}", task_id);
+
+            // fail randomly with chance
+            if rand::random::<f32>() < random_error_chance {
+                error!("Random error");
+                panic!("random error");
+            }
+
+            let saved = worker.process(job).and_then(|image| {
+                info!("Job processed successfully");
+
+                save_image_with_path(output, &image)
+                    .map_err(|e| ProcessError::transient(format!("unable to save output: {}", e)))
+            });
+
+            match saved {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    warn!("Error processing job: {}", e);
+
+                    Err((e.into(), vec![]))
                 }
-                Err(failed_tasks_ids) => {
-                    warn!("Parents were marked as completed, but were not found in the database, ids: {:?}", failed_tasks_ids);
-                    for failed_task_id in failed_tasks_ids {
-                        journal.mark_task_as_failed(failed_task_id).unwrap();
-                    }
+            }
+        }
+        Err(failed_tasks_ids) if failed_tasks_ids.is_empty() => {
+            warn!("Job of task {} is not supported by worker", task_id);
+
+            Err((ProcessError::permanent("job is not supported by worker").into(), vec![]))
+        }
+        Err(failed_tasks_ids) => {
+            warn!("Parents were marked as completed, but were not found in the database, ids: {:?}", failed_tasks_ids);
+
+            let failure = Failure::new(FailureKind::MissingInput, format!("outputs of parents {:?} can't be loaded", failed_tasks_ids));
+            let lost_outputs = failed_tasks_ids.into_iter().map(|id| (id, parent_claims.get(&id).copied().flatten())).collect();
+            Err((failure, lost_outputs))
+        }
+    };
+
+    std::panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or_else(|payload| {
+        let message = panic_message(payload.as_ref());
+        error!("Worker panicked while processing task {}: {}", task_id, message);
+
+        Err((Failure::new(FailureKind::Panic, format!("worker panicked: {}", message)), vec![]))
+    })
+}
+
+/// Message passed to `panic!`, if it was a string.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Identity of current worker thread recorded in the journal, unique across processes on the same machine.
+fn worker_id() -> String {
+    format!("{}@{}", std::thread::current().name().unwrap_or("unnamed"), std::process::id())
+}
 
-                    Err(())
+/// Retries `write` while database is unreachable, for at most [`DATABASE_RETRY_TIMEOUT`].
+/// Connection of the journal is replaced with new one from the pool on next try.
+fn retry_on_connection_error<T>(mut write: impl FnMut() -> Result<T, ErrorType>) -> Result<T, ErrorType> {
+    let deadline = Instant::now() + DATABASE_RETRY_TIMEOUT;
+
+    loop {
+        match write() {
+            Err(ErrorType::DatabaseConnectionError(e)) if Instant::now() < deadline => {
+                warn!("Database is unreachable ({}), retrying...", e);
+                std::thread::sleep(DATABASE_RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs `f` while renewing lease of the running task in background thread,
+/// so long jobs are not timeouted as long as worker is alive.
+fn with_lease<T>(journal: &mut Database, task_id: i64, claim_token: i64, f: impl FnOnce() -> T) -> T {
+    let (done, done_rx) = mpsc::channel::<()>();
+
+    std::thread::scope(|scope| {
+        // runs until job finishes (or panics) and drops the sender
+        scope.spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = done_rx.recv_timeout(LEASE_RENEW_INTERVAL) {
+                match journal.renew_lease(task_id, claim_token) {
+                    Ok(true) => {}
+                    Ok(false) => warn!("Task {} is no longer running, lease not renewed", task_id),
+                    Err(e) => warn!("Unable to renew lease of task {}: {}", task_id, e),
                 }
-            };
+            }
+        });
+
+        let result = f();
+        drop(done);
+        result
+    })
+}
+
+/// Pool of worker threads of the same type.
+/// Tasks are spread round-robin across members that are still alive.
+pub struct WorkerPool<Worker: ImageWorker + Send> {
+    threads: Vec<WorkerThread<Worker>>,
+    next: usize,
+}
+
+impl<Worker: ImageWorker + Send + 'static> WorkerPool<Worker> {
+    pub fn new(name: &str, size: usize) -> Self {
+        Self {
+            threads: (0..size).map(|index| WorkerThread::new(format!("{}-{}", name, index), name)).collect(),
+            next: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn send_task(&mut self, task: Task) -> Result<(), ErrorType> {
+        // pick next member that is alive, starting from the one after last used
+        let alive = (0..self.threads.len())
+            .map(|offset| (self.next + offset) % self.threads.len())
+            .find(|&index| !self.threads[index].thread_died());
+
+        match alive {
+            Some(index) => {
+                self.next = (index + 1) % self.threads.len();
+                self.threads[index].send_task(task)
+            }
+            None => Err(ErrorType::WorkerThreadFailed),
+        }
+    }
+
+    /// See [`WorkerThread::run_in_process`].
+    pub fn run_in_processes(&mut self, name: &str) {
+        for thread in &mut self.threads {
+            thread.run_in_process(name);
+        }
+    }
+
+    /// See [`WorkerThread::claim_own_tasks`].
+    pub fn claim_own_tasks(&mut self, kinds: &[String]) {
+        for thread in &mut self.threads {
+            thread.claim_own_tasks(kinds);
+        }
+    }
+
+    /// Stops all threads of the pool, see [`WorkerThread::stop`].
+    pub fn stop(&mut self) {
+        for thread in &mut self.threads {
+            thread.stop();
+        }
+    }
+
+    /// Joins stopped threads, returning tasks of threads that didn't finish before `deadline`.
+    pub fn join(&mut self, deadline: Instant) -> Vec<(i64, i64)> {
+        self.threads.iter_mut().filter_map(|thread| thread.join(deadline)).collect()
+    }
+
+    pub fn restore_threads<F>(&mut self, mut f: F)
+    where
+        F: FnMut() -> Result<(Worker, Database, ConfigType), ErrorType>,
+    {
+        for thread in &mut self.threads {
+            thread.restore_thread(&mut f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::{mpsc, Arc, RwLock}, time::{Duration, Instant}};
+
+    use image::RgbImage;
+    use serial_test::serial;
+
+    use crate::database::common::{open_connection, Database};
+    use crate::database::repositories::task::{InsertableTask, InsertableTaskTree, Task};
+    use crate::database::schema::{FailureKind, Status};
+    use crate::processing::data_loader::save_image_with_path;
+    use crate::processing::job::{kinds, Job, JobType};
+    use crate::temp::from_temp;
+    use crate::tests_common::*;
+
+    use super::{outbox::Outbox, worker2::{Worker2, Worker2Job}, ImageWorker, ProcessError, WorkerErrorConfig, WorkerPool, WorkerThread};
+
+    /// Worker that panics on its first job and returns input unchanged on next ones.
+    struct PanickingOnce {
+        panicked: bool,
+    }
+
+    impl ImageWorker for PanickingOnce {
+        type WorkerJob = Worker2Job;
+
+        fn process(&mut self, job: Job<Self::WorkerJob>) -> Result<RgbImage, ProcessError> {
+            if !self.panicked {
+                self.panicked = true;
+                panic!("broken image");
+            }
+            Ok(job.data[0].clone())
+        }
+    }
+
+    /// Claims `count` blur tasks, each with its own input image.
+    fn claim_blur_tasks(db: &mut Database, count: usize) -> Vec<Task> {
+        let input = from_temp("input-test.bmp");
+        save_image_with_path(&input, &RgbImage::new(4, 4)).unwrap();
 
-            match result {
-                Ok(()) => journal.mark_task_as_completed(task_id, &filename).unwrap(),
-                Err(()) => journal.mark_task_as_failed(task_id).unwrap(),
+        for _ in 0..count {
+            db.insert_new_task_tree(&InsertableTaskTree {
+                data: None,
+                status: Status::Pending,
+                params: JobType::new_blur(1.0),
+                parent_tasks: vec![InsertableTaskTree::input(&input)],
+                priority: 0,
+            }).unwrap();
+        }
+
+        db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap()
+    }
+
+    /// Waits until all `tasks` are finished, successfully or not.
+    fn wait_for_results(db: &mut Database, tasks: &[Task]) -> Vec<Task> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        loop {
+            let states = tasks.iter().map(|task| db.get_last_task_state(task.task_id).unwrap()).collect::<Vec<_>>();
+            if states.iter().all(|task| matches!(task.status, Status::Completed | Status::Failed | Status::Dead)) {
+                return states;
             }
+            assert!(Instant::now() < deadline, "tasks were not finished: {:?}", states.iter().map(|task| task.status).collect::<Vec<_>>());
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn running_config() -> crate::engine::ConfigType {
+        Arc::new(RwLock::new(WorkerErrorConfig { paused: false, throttle: Duration::ZERO, ..WorkerErrorConfig::default() }))
+    }
+
+    #[test]
+    #[serial]
+    fn pool_sends_tasks_round_robin_skipping_dead_threads() {
+        let mut db = init_database();
+        for _ in 0..4 {
+            db.insert_new_task(&InsertableTask {
+                parent_ids: vec![],
+                status: Status::Pending,
+                data: None,
+                params: JobType::new_blur(1.0),
+                priority: 0,
+            }).unwrap();
+        }
+        let tasks = db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap();
+        let task_ids = tasks.iter().map(|task| task.task_id).collect::<Vec<_>>();
+
+        // members forward received tasks, the middle one is dead
+        let mut pool = WorkerPool::<Worker2>::new("test", 3);
+        let (sink, received) = mpsc::channel();
+        for (index, thread) in pool.threads.iter_mut().enumerate() {
+            let (tx, rx) = mpsc::channel::<Task>();
+            let sink = sink.clone();
+            let handle = std::thread::spawn(move || {
+                if index == 1 {
+                    return;
+                }
+                for task in rx {
+                    sink.send((index, task.task_id)).unwrap();
+                }
+            });
+            thread.thread = Some((handle, Some(tx)));
+        }
+        drop(sink);
+        while !pool.threads[1].thread_died() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        for task in tasks {
+            pool.send_task(task).unwrap();
+        }
+        pool.stop();
+
+        let mut by_thread: HashMap<usize, Vec<i64>> = HashMap::new();
+        for (index, task_id) in received {
+            by_thread.entry(index).or_default().push(task_id);
+        }
+        assert_eq!(by_thread[&0], vec![task_ids[0], task_ids[2]]);
+        assert_eq!(by_thread[&2], vec![task_ids[1], task_ids[3]]);
+        assert!(!by_thread.contains_key(&1));
+
+        // no member is alive
+        db.insert_new_task(&InsertableTask { parent_ids: vec![], status: Status::Pending, data: None, params: JobType::new_blur(1.0), priority: 0 }).unwrap();
+        let task = db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap().pop().unwrap();
+        let mut dead = WorkerPool::<Worker2>::new("dead", 2);
+        assert!(dead.send_task(task).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn panic_fails_only_its_task() {
+        let mut db = init_database();
+        let tasks = claim_blur_tasks(&mut db, 2);
 
-            // sleep
-            std::thread::sleep(config.read().unwrap().throttle);
+        let mut thread = WorkerThread::<PanickingOnce>::new("panicking-0".to_string(), "panicking");
+        thread.start(PanickingOnce { panicked: false }, open_connection().unwrap(), Outbox::open("panicking-0").unwrap(), running_config());
+        for task in tasks.clone() {
+            thread.send_task(task).unwrap();
         }
+
+        let started = Instant::now();
+        let results = wait_for_results(&mut db, &tasks);
+
+        // failed right away, not by expired lease
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(results[0].status, Status::Failed);
+        assert_eq!(results[0].failure_kind, Some(FailureKind::Panic));
+        assert_eq!(results[0].error_message.as_deref(), Some("worker panicked: broken image"));
+
+        // the same thread processed next task
+        assert_eq!(results[1].status, Status::Completed);
+        assert!(!thread.thread_died());
+        assert_eq!(results[1].worker, results[0].worker);
+
+        thread.stop();
+        assert!(thread.join(Instant::now() + Duration::from_secs(5)).is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn paused_pipeline_does_not_hold_queue() {
+        let mut db = init_database();
+        let tasks = claim_blur_tasks(&mut db, 2);
+        db.pause_pipeline(tasks[0].task_id).unwrap();
+
+        // worker already panicked, so it only copies its input
+        let mut thread = WorkerThread::<PanickingOnce>::new("paused-0".to_string(), "paused");
+        thread.start(PanickingOnce { panicked: true }, open_connection().unwrap(), Outbox::open("paused-0").unwrap(), running_config());
+        for task in tasks.clone() {
+            thread.send_task(task).unwrap();
+        }
+
+        let results = wait_for_results(&mut db, &tasks[1..]);
+
+        assert_eq!(results[0].status, Status::Completed);
+        assert_eq!(db.get_last_task_state(tasks[0].task_id).unwrap().status, Status::Pending);
+        assert!(db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap().is_empty());
+
+        thread.stop();
+        assert!(thread.join(Instant::now() + Duration::from_secs(5)).is_none());
     }
 }
@@ -0,0 +1,346 @@
+//! Outbox of worker thread - results of tasks that couldn't be written to the journal because database
+//! was unreachable. They are kept in a local file, one JSON line per result, and written to the journal
+//! in order once database is back, so finished work is not lost.
+//!
+//! Replay is idempotent - journal accepts result of a claim only once and ignores it when it comes again,
+//! so result that was written just before the outbox was saved is not recorded twice. Output of parent
+//! is failed as lost only if it still comes from the same claim, output of its newer run is kept.
+
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, BufRead, BufReader, Write},
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::common::{Database, ErrorType},
+    temp::from_temp,
+};
+
+use super::JobResult;
+
+/// Result of a task to be written to the journal.
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub task_id: i64,
+    pub claim_token: i64,
+    /// File the output was saved to.
+    pub output: String,
+    pub result: JobResult,
+}
+
+impl Report {
+    fn write(&self, journal: &mut Database) -> Result<(), ErrorType> {
+        match &self.result {
+            Ok(()) => journal.mark_task_as_completed(self.task_id, self.claim_token, &self.output),
+            Err((failure, failed_tasks_ids)) => {
+                for &(failed_task_id, completed_by) in failed_tasks_ids {
+                    match journal.mark_output_as_lost(failed_task_id, completed_by) {
+                        Ok(()) => {}
+                        Err(e @ ErrorType::DatabaseConnectionError(_)) => return Err(e),
+                        Err(e) => warn!("Unable to mark parent {} as failed: {}", failed_task_id, e),
+                    }
+                }
+                journal.mark_task_as_failed(self.task_id, self.claim_token, failure.clone())
+            }
+        }
+    }
+
+    /// Writes report to the journal. Fails only if database is unreachable, result rejected by the journal is dropped.
+    fn deliver(&self, journal: &mut Database) -> Result<(), ErrorType> {
+        match self.write(journal) {
+            Ok(()) => {}
+            Err(e @ ErrorType::DatabaseConnectionError(_)) => return Err(e),
+            Err(ErrorType::TaskCancelled(_)) => {
+                info!("Task {} was cancelled, dropping result", self.task_id);
+                // output may not exist if processing failed
+                fs::remove_file(&self.output).ok();
+            }
+            // task was re-dispatched in the meantime, result of this worker is dropped
+            Err(ErrorType::StaleClaim(_)) => warn!("Result of task {} dropped, claim {} is outdated", self.task_id, self.claim_token),
+            // task is recovered when its lease expires
+            Err(e) => error!("Unable to report result of task {}: {}", self.task_id, e),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Outbox {
+    path: String,
+    /// Lock file held for as long as the outbox is open, so other process on the same machine takes other outbox.
+    _lock: File,
+    /// Reports waiting for database, in order they were made. The same as content of the file.
+    pending: Vec<Report>,
+}
+
+impl Outbox {
+    /// Opens outbox of worker thread `name` in temp directory, with reports left there by previous run.
+    /// If outbox of that name is used by other process, next free one is taken.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let mut slot = 0;
+
+        loop {
+            let path = match slot {
+                0 => from_temp(&format!("outbox-{}.jsonl", name)),
+                _ => from_temp(&format!("outbox-{}.{}.jsonl", name, slot)),
+            };
+            let lock = OpenOptions::new().create(true).truncate(false).write(true).open(format!("{}.lock", path))?;
+
+            match lock.try_lock() {
+                Ok(()) => {
+                    let pending = load(&path)?;
+                    if !pending.is_empty() {
+                        info!("Found {} results of tasks waiting in {}", pending.len(), path);
+                    }
+
+                    return Ok(Self { path, _lock: lock, pending });
+                }
+                Err(TryLockError::WouldBlock) => slot += 1,
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Writes result of a task to the journal, or keeps it in the outbox if database is unreachable.
+    /// Results already waiting in the outbox are written first, so results are recorded in order.
+    pub fn report(&mut self, journal: &mut Database, report: Report) {
+        if let Err(e) = self.replay(journal).and_then(|()| report.deliver(journal)) {
+            warn!("Unable to report result of task {} ({}), keeping it in outbox", report.task_id, e);
+
+            if let Err(e) = self.push(report) {
+                // task is recovered when its lease expires
+                error!("Unable to save result to outbox {}: {}", self.path, e);
+            }
+        }
+    }
+
+    /// Writes waiting reports to the journal in order, until database turns out to be unreachable.
+    pub fn replay(&mut self, journal: &mut Database) -> Result<(), ErrorType> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let mut written = 0;
+        let mut result = Ok(());
+
+        for report in &self.pending {
+            result = report.deliver(journal);
+            if result.is_err() {
+                break;
+            }
+            written += 1;
+        }
+
+        if written > 0 {
+            info!("Replayed {} results of tasks from outbox", written);
+            self.pending.drain(..written);
+
+            // replayed reports are ignored by the journal if they come again
+            if let Err(e) = self.save() {
+                warn!("Unable to save outbox {}: {}", self.path, e);
+            }
+        }
+
+        result
+    }
+
+    /// Appends report to the file, it is on disk when this returns.
+    fn push(&mut self, report: Report) -> io::Result<()> {
+        let mut line = serde_json::to_string(&report)?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        self.pending.push(report);
+
+        Ok(())
+    }
+
+    /// Replaces the file with waiting reports, so it is never left half written.
+    fn save(&self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return fs::remove_file(&self.path);
+        }
+
+        let staging = format!("{}.tmp", self.path);
+        let mut file = File::create(&staging)?;
+        for report in &self.pending {
+            serde_json::to_writer(&mut file, report)?;
+            file.write_all(b"\n")?;
+        }
+        file.sync_data()?;
+
+        fs::rename(staging, &self.path)
+    }
+}
+
+/// Reads reports from outbox file. Line that was not written completely (process died meanwhile) is skipped.
+fn load(path: &str) -> io::Result<Vec<Report>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut reports = vec![];
+    for line in BufReader::new(file).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(report) => reports.push(report),
+            Err(e) => warn!("Skipping broken line of outbox {}: {}", path, e),
+        }
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serial_test::serial;
+
+    use crate::database::common::{open_connection, open_session, Database, ErrorType};
+    use crate::database::repositories::task::{Failure, RetryPolicy, Task};
+    use crate::database::schema::{FailureKind, Status};
+    use crate::processing::job::kinds;
+    use crate::temp::from_temp;
+    use crate::tests_common::*;
+
+    use super::{Outbox, Report};
+
+    fn test_failure() -> Failure {
+        Failure::new(FailureKind::ProcessError, "test failure")
+    }
+
+    #[test]
+    #[serial]
+    fn repeated_result_is_recorded_once() {
+        let mut db = init_database();
+
+        db.insert_new_task_tree(&EXAMPLE_TASK_TREE1).unwrap();
+
+        let completed = db.claim_runnable_tasks_of_kinds(&[kinds::RESIZE], None).unwrap().pop().unwrap();
+        let failed = db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap().pop().unwrap();
+        db.mark_task_as_running(completed.task_id, completed.claim_token.unwrap(), "test-worker").unwrap();
+        db.mark_task_as_running(failed.task_id, failed.claim_token.unwrap(), "test-worker").unwrap();
+
+        for _ in 0..2 {
+            db.mark_task_as_completed(completed.task_id, completed.claim_token.unwrap(), "out.bmp").unwrap();
+            db.mark_task_as_failed(failed.task_id, failed.claim_token.unwrap(), test_failure()).unwrap();
+        }
+
+        let count = |db: &mut Database, task_id: i64, status: Status| -> i64 {
+            const QUERY: &str = "SELECT COUNT(*) FROM tasks WHERE task_id = $1 AND status = $2";
+            db.client().unwrap().query_one(QUERY, &[&task_id, &status]).unwrap().get(0)
+        };
+        assert_eq!(count(&mut db, completed.task_id, Status::Completed), 1);
+        assert_eq!(count(&mut db, failed.task_id, Status::Failed), 1);
+        assert_eq!(db.get_last_task_state(failed.task_id).unwrap().attempt, 1);
+
+        // other output is not the same result
+        assert!(matches!(
+            db.mark_task_as_completed(completed.task_id, completed.claim_token.unwrap(), "other.bmp"),
+            Err(ErrorType::StaleClaim(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn result_is_kept_in_outbox_until_database_is_back() {
+        let mut db = init_database();
+        let mut admin = open_session().unwrap();
+
+        db.insert_new_task_tree(&EXAMPLE_TASK_TREE1).unwrap();
+
+        let mut tasks = db.claim_runnable_tasks_of_kinds(&[kinds::RESIZE], None).unwrap();
+        tasks.extend(db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap());
+        for task in &tasks {
+            db.mark_task_as_running(task.task_id, task.claim_token.unwrap(), "test-worker").unwrap();
+        }
+        let report = |task: &Task| Report {
+            task_id: task.task_id,
+            claim_token: task.claim_token.unwrap(),
+            output: format!("{}.bmp", task.task_id),
+            result: Ok(()),
+        };
+
+        std::fs::remove_file(from_temp("outbox-test.jsonl")).ok();
+        let mut outbox = Outbox::open("test").unwrap();
+
+        // connection of worker is closed, e.g. by database restart
+        let mut journal = open_session().unwrap();
+        let pid: i32 = journal.client().unwrap().query_one("SELECT pg_backend_pid()", &[]).unwrap().get(0);
+        admin.execute("SELECT pg_terminate_backend($1)", &[&pid]).unwrap();
+        while journal.is_valid(Duration::from_secs(1)).is_ok() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        outbox.report(&mut journal, report(&tasks[0]));
+        assert!(!outbox.is_empty());
+        assert_eq!(db.get_last_task_state(tasks[0].task_id).unwrap().status, Status::Running);
+
+        // results survive restart of the worker
+        drop(outbox);
+        let mut outbox = Outbox::open("test").unwrap();
+        assert!(!outbox.is_empty());
+
+        let mut journal = open_connection().unwrap();
+        outbox.report(&mut journal, report(&tasks[1]));
+        assert!(outbox.is_empty());
+
+        for task in &tasks {
+            let state = db.get_last_task_state(task.task_id).unwrap();
+            assert_eq!(state.status, Status::Completed);
+            assert_eq!(state.data, Some(format!("{}.bmp", task.task_id)));
+        }
+
+        // replaying again has no effect
+        Outbox::open("test").unwrap().replay(&mut journal).unwrap();
+        assert!(!std::path::Path::new(&from_temp("outbox-test.jsonl")).exists());
+    }
+
+    #[test]
+    #[serial]
+    fn replayed_failure_keeps_newer_output_of_parent() {
+        let mut db = init_database();
+        db.retry_policy = RetryPolicy { max_attempts: 5, backoff: Duration::ZERO, max_backoff: Duration::ZERO };
+
+        db.insert_new_task_tree(&EXAMPLE_TASK_TREE1).unwrap();
+
+        let parent = db.claim_runnable_tasks_of_kinds(&[kinds::RESIZE], None).unwrap().pop().unwrap();
+        let child = db.claim_runnable_tasks_of_kinds(&[kinds::BLUR], None).unwrap().pop().unwrap();
+        db.mark_task_as_running(parent.task_id, parent.claim_token.unwrap(), "test-worker").unwrap();
+        db.mark_task_as_completed(parent.task_id, parent.claim_token.unwrap(), "old.bmp").unwrap();
+        db.mark_task_as_running(child.task_id, child.claim_token.unwrap(), "test-worker").unwrap();
+
+        // while report waited in outbox, parent was run again by other worker
+        db.mark_output_as_lost(parent.task_id, parent.claim_token).unwrap();
+        let rerun = db.claim_runnable_tasks_of_kinds(&[kinds::RESIZE], None).unwrap().pop().unwrap();
+        db.mark_task_as_running(rerun.task_id, rerun.claim_token.unwrap(), "test-worker").unwrap();
+        db.mark_task_as_completed(rerun.task_id, rerun.claim_token.unwrap(), "new.bmp").unwrap();
+
+        std::fs::remove_file(from_temp("outbox-lost-test.jsonl")).ok();
+        let mut outbox = Outbox::open("lost-test").unwrap();
+        let failure = Failure::new(FailureKind::MissingInput, "output of parent can't be loaded");
+        outbox.report(&mut db, Report {
+            task_id: child.task_id,
+            claim_token: child.claim_token.unwrap(),
+            output: "child.bmp".to_string(),
+            result: Err((failure, vec![(parent.task_id, parent.claim_token)])),
+        });
+
+        let state = db.get_last_task_state(parent.task_id).unwrap();
+        assert_eq!(state.status, Status::Completed);
+        assert_eq!(state.data, Some("new.bmp".to_string()));
+        assert_eq!(db.get_last_task_state(child.task_id).unwrap().status, Status::Failed);
+    }
+}
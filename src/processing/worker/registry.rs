@@ -0,0 +1,195 @@
+use std::{io, time::Instant};
+
+use log::info;
+
+use crate::{
+    database::{
+        common::{open_connection, ErrorType},
+        repositories::task::Task,
+    },
+    engine::ConfigType,
+};
+
+use super::{subprocess, ImageWorker, WorkerPool};
+
+/// Pool of worker threads with erased worker type, so pools of diffrent workers can be stored together.
+pub trait DynWorkerPool: Send {
+    fn is_empty(&self) -> bool;
+    fn len(&self) -> usize;
+    fn send_task(&mut self, task: Task) -> Result<(), ErrorType>;
+    /// Restarts dead threads, their connections claim tasks for engine `epoch`.
+    fn restore_threads(&mut self, config: &ConfigType, epoch: i64);
+    fn claim_own_tasks(&mut self, kinds: &[String]);
+    fn run_in_processes(&mut self, name: &str);
+    /// Serves jobs in child process, see [`subprocess`].
+    fn serve_process(&self) -> io::Result<()>;
+    fn stop(&mut self);
+    fn join(&mut self, deadline: Instant) -> Vec<(i64, i64)>;
+}
+
+struct FactoryPool<Worker: ImageWorker + Send, F> {
+    pool: WorkerPool<Worker>,
+    factory: F,
+}
+
+impl<Worker, F> DynWorkerPool for FactoryPool<Worker, F>
+where
+    Worker: ImageWorker + Send + 'static,
+    F: Fn() -> Worker + Send,
+{
+    fn is_empty(&self) -> bool {
+        self.pool.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.pool.len()
+    }
+
+    fn send_task(&mut self, task: Task) -> Result<(), ErrorType> {
+        self.pool.send_task(task)
+    }
+
+    fn restore_threads(&mut self, config: &ConfigType, epoch: i64) {
+        let factory = &self.factory;
+        self.pool.restore_threads(|| {
+            let mut journal = open_connection()?;
+            journal.epoch = Some(epoch);
+            Ok((factory(), journal, config.clone()))
+        });
+    }
+
+    fn claim_own_tasks(&mut self, kinds: &[String]) {
+        self.pool.claim_own_tasks(kinds)
+    }
+
+    fn run_in_processes(&mut self, name: &str) {
+        self.pool.run_in_processes(name)
+    }
+
+    fn serve_process(&self) -> io::Result<()> {
+        subprocess::serve((self.factory)())
+    }
+
+    fn stop(&mut self) {
+        self.pool.stop()
+    }
+
+    fn join(&mut self, deadline: Instant) -> Vec<(i64, i64)> {
+        self.pool.join(deadline)
+    }
+}
+
+/// Worker registered in engine together with job kinds it accepts.
+pub struct WorkerRegistration {
+    pub name: String,
+    pub kinds: Vec<String>,
+    pub pool: Box<dyn DynWorkerPool>,
+}
+
+/// Set of workers used by engine. Scheduler claims tasks for every registered worker by its job kinds.
+/// Kinds are names of operations (see [`kinds`](crate::processing::job::kinds) for built-in ones), so worker
+/// of new operation is plugged in by registering it for its kind, without changes in the engine.
+#[derive(Default)]
+pub struct WorkerRegistry {
+    workers: Vec<WorkerRegistration>,
+}
+
+impl WorkerRegistry {
+    pub fn new() -> Self {
+        Self { workers: vec![] }
+    }
+
+    /// Registers pool of `threads` workers created with `factory` that will receive tasks of given `kinds`.
+    /// Every kind is processed by one worker only.
+    pub fn register<Worker, F>(mut self, name: &str, kinds: &[&str], threads: usize, factory: F) -> Self
+    where
+        Worker: ImageWorker + Send + 'static,
+        F: Fn() -> Worker + Send + 'static,
+    {
+        info!("Registering worker '{}' with {} threads for {:?}", name, threads, kinds);
+
+        for kind in kinds {
+            // kind is read from serialized params in SQL as a word
+            assert!(
+                !kind.is_empty() && kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "job kind '{}' has to be a word",
+                kind
+            );
+            if let Some(worker) = self.worker_of_kind(kind) {
+                panic!("job kind '{}' is already registered for worker '{}'", kind, worker.name);
+            }
+        }
+
+        self.workers.push(WorkerRegistration {
+            name: name.to_string(),
+            kinds: kinds.iter().map(|kind| kind.to_string()).collect(),
+            pool: Box::new(FactoryPool {
+                pool: WorkerPool::new(name, threads),
+                factory,
+            }),
+        });
+
+        self
+    }
+
+    /// Makes every registered worker run its jobs in child processes, so crash of a job doesn't take down the app.
+    pub fn run_in_processes(mut self) -> Self {
+        for worker in &mut self.workers {
+            worker.pool.run_in_processes(&worker.name);
+        }
+
+        self
+    }
+
+    /// Serves jobs of worker `name` in this process, used by children started by [`run_in_processes`](Self::run_in_processes).
+    pub fn serve_worker_process(&self, name: &str) -> io::Result<()> {
+        match self.workers.iter().find(|worker| worker.name == name) {
+            Some(worker) => worker.pool.serve_process(),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("worker '{}' is not registered", name))),
+        }
+    }
+
+    /// Worker registered for job `kind`.
+    pub fn worker_of_kind(&self, kind: &str) -> Option<&WorkerRegistration> {
+        self.workers.iter().find(|worker| worker.kinds.iter().any(|registered| registered == kind))
+    }
+
+    /// Number of worker threads of all registered workers, each of them holds one database connection.
+    pub fn thread_count(&self) -> usize {
+        self.workers.iter().map(|worker| worker.pool.len()).sum()
+    }
+
+    /// Names of registered workers, in order of registration.
+    pub fn names(&self) -> Vec<String> {
+        self.workers.iter().map(|worker| worker.name.clone()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &WorkerRegistration> {
+        self.workers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut WorkerRegistration> {
+        self.workers.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processing::job::kinds;
+    use crate::processing::worker::{default_registry, worker1::Worker1};
+
+    #[test]
+    fn worker_is_found_by_registered_kind() {
+        let registry = default_registry(1, 1).register("sharpener", &["Sharpen"], 1, Worker1::new);
+
+        assert_eq!(registry.worker_of_kind(kinds::BLUR).unwrap().name, "worker2");
+        assert_eq!(registry.worker_of_kind("Sharpen").unwrap().name, "sharpener");
+        assert!(registry.worker_of_kind("Unknown").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered")]
+    fn kind_is_registered_once() {
+        default_registry(1, 1).register("other", &[kinds::BLUR], 1, Worker1::new);
+    }
+}
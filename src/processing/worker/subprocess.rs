@@ -0,0 +1,282 @@
+//! Running jobs in child processes, so abort, stack overflow or out-of-memory while processing
+//! kills only the child and not the whole app.
+//!
+//! Child is the same executable started with hidden `--worker-process <NAME>` argument.
+//! It reads one JSON request per line from stdin and answers with one JSON line on stdout.
+//! Child that doesn't answer until job deadline is killed.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{
+    repositories::task::{Failure, Task},
+    schema::FailureKind,
+};
+
+use super::{execute_job, ImageWorker, JobResult};
+
+/// Command line argument that starts executable as child worker process.
+pub const WORKER_PROCESS_ARG: &str = "--worker-process";
+/// How long child process can work on a single job before it is killed.
+pub const JOB_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct ChildRequest {
+    task: Task,
+    output: String,
+    random_error_chance: f32,
+}
+
+struct RunningChild {
+    process: Child,
+    stdin: ChildStdin,
+    /// Lines read from stdout of the child by its reader thread, closed when child closes its output.
+    responses: Receiver<io::Result<String>>,
+}
+
+impl Drop for RunningChild {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+/// Child process running jobs of one worker thread. It is started on first job and started again after it dies.
+pub struct ChildWorker {
+    name: String,
+    child: Option<RunningChild>,
+    /// Builds command that starts the child.
+    command: fn(&str) -> io::Result<Command>,
+    deadline: Duration,
+}
+
+/// Starts current executable as child worker process.
+fn worker_process_command(name: &str) -> io::Result<Command> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command.arg(WORKER_PROCESS_ARG).arg(name);
+    Ok(command)
+}
+
+enum ExchangeError {
+    Io(io::Error),
+    DeadlineExceeded,
+}
+
+impl From<io::Error> for ExchangeError {
+    fn from(e: io::Error) -> Self {
+        ExchangeError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ExchangeError {
+    fn from(e: serde_json::Error) -> Self {
+        ExchangeError::Io(e.into())
+    }
+}
+
+impl ChildWorker {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            child: None,
+            command: worker_process_command,
+            deadline: JOB_DEADLINE,
+        }
+    }
+
+    fn spawn(&self) -> io::Result<RunningChild> {
+        let mut process = (self.command)(&self.name)?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        info!("Started worker process {} ({})", self.name, process.id());
+
+        // blocking reads are done on separate thread, so waiting for answer can time out
+        let stdout = BufReader::new(process.stdout.take().expect("stdout of child is piped"));
+        let (sender, responses) = mpsc::channel();
+        thread::Builder::new().name(format!("{}-reader", self.name)).spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+        Ok(RunningChild {
+            stdin: process.stdin.take().expect("stdin of child is piped"),
+            responses,
+            process,
+        })
+    }
+
+    /// Runs job in child process. If child dies while processing, task fails with its exit status.
+    /// If it doesn't answer until deadline, it is killed and task fails as timeouted.
+    pub fn execute(&mut self, task: Task, output: &str, random_error_chance: f32) -> JobResult {
+        let child = match self.child.take() {
+            Some(child) => child,
+            None => match self.spawn() {
+                Ok(child) => child,
+                Err(e) => return Err((Failure::new(FailureKind::Crash, format!("unable to start worker process: {}", e)), vec![])),
+            },
+        };
+        let child = self.child.insert(child);
+
+        let request = ChildRequest {
+            task,
+            output: output.to_string(),
+            random_error_chance,
+        };
+
+        let error = match Self::exchange(child, &request, self.deadline) {
+            Ok(result) => return result,
+            Err(e) => e,
+        };
+
+        // child is killed if it is still alive but broke the protocol or is stuck, exit status tells what happened to it
+        let mut child = self.child.take().expect("child was started");
+        child.process.kill().ok();
+        let status = match child.process.wait() {
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        match error {
+            ExchangeError::DeadlineExceeded => {
+                error!("Worker process {} didn't finish job in {:?}, killed", self.name, self.deadline);
+
+                Err((Failure::new(FailureKind::Timeout, format!("worker process didn't finish job in {:?}", self.deadline)), vec![]))
+            }
+            ExchangeError::Io(e) => {
+                error!("Worker process {} failed ({}): {}", self.name, e, status);
+
+                Err((Failure::new(FailureKind::Crash, format!("worker process exited with {}", status)), vec![]))
+            }
+        }
+    }
+
+    fn exchange(child: &mut RunningChild, request: &ChildRequest, deadline: Duration) -> Result<JobResult, ExchangeError> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        child.stdin.write_all(line.as_bytes())?;
+        child.stdin.flush()?;
+
+        let response = match child.responses.recv_timeout(deadline) {
+            Ok(response) => response?,
+            Err(RecvTimeoutError::Timeout) => return Err(ExchangeError::DeadlineExceeded),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "worker process closed its output").into());
+            }
+        };
+
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+/// Body of child process - serves jobs sent over stdin until it is closed.
+pub fn serve<Worker: ImageWorker>(mut worker: Worker) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    for line in io::stdin().lock().lines() {
+        let request: ChildRequest = serde_json::from_str(&line?)?;
+
+        let result = execute_job(&mut worker, request.task, &request.output, request.random_error_chance);
+
+        serde_json::to_writer(&mut stdout, &result)?;
+        stdout.write_all(b"\n")?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, process::Command, time::Duration};
+
+    use serial_test::serial;
+
+    use crate::database::{
+        repositories::task::{InsertableTaskTree, Task},
+        schema::{FailureKind, Status},
+    };
+    use crate::processing::job::{kinds, JobType};
+    use crate::temp::from_temp;
+    use crate::tests_common::*;
+
+    use super::{ChildWorker, JOB_DEADLINE};
+
+    /// Child worker started by `command` instead of worker process.
+    fn script_worker(name: &str, command: fn(&str) -> io::Result<Command>, deadline: Duration) -> ChildWorker {
+        ChildWorker { name: name.to_string(), child: None, command, deadline }
+    }
+
+    /// Runs shell `script` with worker name passed as `$0`.
+    fn shell(script: &str, name: &str) -> io::Result<Command> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script).arg(name);
+        Ok(command)
+    }
+
+    fn claimed_task() -> Task {
+        let mut db = init_database();
+        db.insert_new_task_tree(&InsertableTaskTree {
+            data: None,
+            status: Status::Pending,
+            params: JobType::new_brightness(1.0),
+            parent_tasks: vec![],
+            priority: 0,
+        }).unwrap();
+
+        db.claim_runnable_tasks_of_kinds(&[kinds::BRIGHTNESS], None).unwrap().remove(0)
+    }
+
+    #[test]
+    #[serial]
+    fn aborted_child_fails_task_as_crash() {
+        let task = claimed_task();
+        let mut worker = script_worker("abort", |name| shell("read line; kill -ABRT $$", name), JOB_DEADLINE);
+
+        let (failure, _) = worker.execute(task, "out.bmp", 0.0).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::Crash);
+        assert!(failure.message.contains("SIGABRT"), "{}", failure.message);
+    }
+
+    #[test]
+    #[serial]
+    fn crashed_child_is_started_again_for_next_job() {
+        let task = claimed_task();
+        let marker = from_temp("crashed-child-marker");
+        std::fs::remove_file(&marker).ok();
+
+        // first child exits with error, the next one finishes the job
+        let mut worker = script_worker(&marker, |name| shell(r#"read line; if [ -e "$0" ]; then echo '{"Ok":null}'; else touch "$0"; exit 3; fi"#, name), JOB_DEADLINE);
+
+        let (failure, _) = worker.execute(task.clone(), "out.bmp", 0.0).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::Crash);
+        assert_eq!(failure.message, "worker process exited with exit status: 3");
+
+        assert!(worker.execute(task, "out.bmp", 0.0).is_ok());
+        std::fs::remove_file(&marker).ok();
+    }
+
+    #[test]
+    #[serial]
+    fn stuck_child_is_killed_after_deadline() {
+        let task = claimed_task();
+        let mut worker = script_worker("stuck", |name| shell("read line; exec sleep 60", name), Duration::from_millis(200));
+
+        let (failure, _) = worker.execute(task, "out.bmp", 0.0).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::Timeout);
+        assert!(worker.child.is_none());
+    }
+}
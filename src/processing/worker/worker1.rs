@@ -2,10 +2,11 @@ use image::RgbImage;
 use image;
 use log::debug;
 
-use crate::{processing::{worker::ImageWorker, job::{self}}};
+use crate::{processing::{worker::{ImageWorker, ProcessError}, job::{self}}};
 
 
 
+#[derive(Default)]
 pub struct Worker1;
 
 #[derive(Debug, Clone, Copy)]
@@ -32,12 +33,16 @@ impl TryFrom<job::JobType> for Worker1Job {
 impl ImageWorker for Worker1 {
     type WorkerJob = Worker1Job;
 
-    fn process(&mut self, job: job::Job<Self::WorkerJob>) -> Result<RgbImage, ()> {
+    fn process(&mut self, job: job::Job<Self::WorkerJob>) -> Result<RgbImage, ProcessError> {
         match job {
             job::Job { task: Worker1Job::Resize(_params), data } => {
                 debug!("Resize {:?}", _params);
                 
-                let img = data.first().unwrap();
+                let img = data.first().ok_or_else(|| ProcessError::permanent("resize needs an input image"))?;
+
+                if _params.0 == 0 || _params.1 == 0 {
+                    return Err(ProcessError::permanent(format!("can't resize to {}x{}", _params.0, _params.1)));
+                }
 
                 Ok(image::imageops::resize(img, 
                                           _params.0, 
@@ -48,26 +53,31 @@ impl ImageWorker for Worker1 {
                 debug!("Crop {:?}", _params);
 
                 // get the first image as &mut 
-                let img = data.first_mut().unwrap(); 
+                let img = data.first_mut().ok_or_else(|| ProcessError::permanent("crop needs an input image"))?; 
+
+                let (x, y, width, height) = (_params.0, _params.1, _params.2, _params.3);
+                let fits = width > 0 && height > 0
+                    && x.checked_add(width).is_some_and(|right| right <= img.width())
+                    && y.checked_add(height).is_some_and(|bottom| bottom <= img.height());
+
+                if !fits {
+                    return Err(ProcessError::permanent(format!(
+                        "crop {}x{} at ({}, {}) is out of {}x{} image", width, height, x, y, img.width(), img.height()
+                    )));
+                }
                 
                 Ok(
-                    image::imageops::crop(img, 
-                                          _params.0, 
-                                         _params.1, 
-                                          _params.2, 
-                                         _params.3).to_image()
+                    image::imageops::crop(img, x, y, width, height).to_image()
                 )
             },
             job::Job { task: Worker1Job::Overlay(_params), mut data } => {
                 debug!("Overlay {:?}", _params);
 
-                // get the first image as &mut
-                let (img, rest) = data.split_first_mut().unwrap();
-
-                //panic!("XD");
-
-                // get the second image as &mut
-                let img2 = rest.first().unwrap();
+                // get the first image as &mut and the second one
+                let (img, img2) = match data.as_mut_slice() {
+                    [img, img2, ..] => (img, img2),
+                    _ => return Err(ProcessError::permanent("overlay needs two input images")),
+                };
 
                 image::imageops::overlay(img, img2, _params.0 as i64, _params.1 as i64);
 
@@ -1,9 +1,10 @@
 use image::RgbImage;
 use log::{debug};
 
-use crate::processing::{worker::ImageWorker, job};
+use crate::processing::{worker::{ImageWorker, ProcessError}, job};
 
 
+#[derive(Default)]
 pub struct Worker2;
 
 #[derive(Debug, Clone, Copy)]
@@ -27,14 +28,14 @@ impl TryFrom<job::JobType> for Worker2Job {
 impl ImageWorker for Worker2 {
     type WorkerJob = Worker2Job;
 
-    fn process(&mut self, job: job::Job<Self::WorkerJob>) -> Result<RgbImage, ()> {
+    fn process(&mut self, job: job::Job<Self::WorkerJob>) -> Result<RgbImage, ProcessError> {
         debug!("Worker1::process()");
 
         match job {
             job::Job { task: Worker2Job::Brightness(_params), data } => {
                 debug!("Brightness {:?}", _params);
                 
-                let img = data.first().unwrap();
+                let img = data.first().ok_or_else(|| ProcessError::permanent("brightness needs an input image"))?;
 
                 Ok(image::imageops::brighten(img, 
                                           _params.0 as i32,))
@@ -43,7 +44,11 @@ impl ImageWorker for Worker2 {
                 debug!("Blur {:?}", _params);
 
                 // get the first image as &mut 
-                let img = data.first_mut().unwrap(); 
+                let img = data.first_mut().ok_or_else(|| ProcessError::permanent("blur needs an input image"))?; 
+
+                if !_params.0.is_finite() || _params.0 < 0.0 {
+                    return Err(ProcessError::permanent(format!("invalid blur sigma {}", _params.0)));
+                }
                 
                 Ok(
                     image::imageops::blur(img, _params.0)
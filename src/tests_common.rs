@@ -23,7 +23,9 @@ pub static EXAMPLE_TASK_TREE1: Lazy<InsertableTaskTree> = Lazy::new(|| Insertabl
                 params: JobType::new_blur(0.0),
 
                 parent_tasks: vec![],
+                priority: 0,
             }],
+            priority: 0,
         },
         InsertableTaskTree {
             data: Some("Subtask 2".to_string()),
@@ -31,8 +33,10 @@ pub static EXAMPLE_TASK_TREE1: Lazy<InsertableTaskTree> = Lazy::new(|| Insertabl
             params: JobType::new_resize(100, 100),
 
             parent_tasks: vec![],
+            priority: 0,
         },
     ],
+    priority: 0,
 });
 
 #[allow(unused)]